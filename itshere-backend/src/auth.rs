@@ -1,37 +1,125 @@
 use serde::{Deserialize,Serialize};
 use jsonwebtoken::{encode, decode, Header, Validation, EncodingKey, DecodingKey};
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use argon2::password_hash::{SaltString, rand_core::OsRng};
+use subtle::ConstantTimeEq;
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use rand::{Rng, RngCore};
+use sha2::{Digest, Sha256};
+use crate::config::config;
 use crate::error::Error;
 use chrono::{Utc, Duration};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
     pub sub: String,
+    /// Id of the session the token was issued for; checked against the
+    /// `sessions` collection so revoked sessions stop working immediately.
+    pub sid: String,
     pub exp: usize, 
 }
 
-const SECRET: &[u8] = b"ioRFHMleDUkukPt2fdxvxCV7KLtoiH7CEyTj5N4NwpT7JimGstwljHLbogRClaXoxGnrgrObHEow4XPqcuKdmw==";
-
-pub fn create_jwt(username: &str) -> Result<String, Error> {
-    let expiration = Utc::now()
-        .checked_add_signed(Duration::hours(24))
-        .expect("valid timestamp")
+pub fn create_jwt(username: &str, session_id: &str) -> Result<String, Error> {
+    let auth = &config().auth;
+    let expiration = Duration::try_hours(auth.token_lifetime_hours)
+        .and_then(|lifetime| Utc::now().checked_add_signed(lifetime))
+        .ok_or(Error::TokenCreation)?
         .timestamp() as usize;
 
     let claims = Claims {
         sub: username.to_owned(),
+        sid: session_id.to_owned(),
         exp: expiration,
     };
 
-    encode(&Header::default(), &claims, &EncodingKey::from_secret(SECRET))
+    encode(&Header::default(), &claims, &EncodingKey::from_secret(auth.jwt_secret.as_bytes()))
         .map_err(|_| Error::TokenCreation)
 }
 
 pub fn verify_jwt(token: &str) -> Result<Claims, Error> {
     decode::<Claims>(
         token,
-        &DecodingKey::from_secret(SECRET),
+        &DecodingKey::from_secret(config().auth.jwt_secret.as_bytes()),
         &Validation::default(),
     )
     .map(|data| data.claims)
     .map_err(|_| Error::InvalidToken)
 }
+
+/// Opaque, high-entropy token handed to the client; only its hash is stored.
+pub fn generate_refresh_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+pub fn hash_refresh_token(token: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Alphabet for handover codes, without look-alikes such as `0`/`O` and `1`/`I`.
+const HANDOVER_ALPHABET: &[u8] = b"23456789ABCDEFGHJKLMNPQRSTUVWXYZ";
+const HANDOVER_CODE_LEN: usize = 8;
+
+/// Short code the owner shows the finder when they meet, read aloud or
+/// scanned from a QR code.
+pub fn generate_handover_code() -> String {
+    let mut rng = rand::thread_rng();
+    (0..HANDOVER_CODE_LEN)
+        .map(|_| HANDOVER_ALPHABET[rng.gen_range(0..HANDOVER_ALPHABET.len())] as char)
+        .collect()
+}
+
+/// Compares a submitted handover code in constant time, ignoring case and
+/// the spaces or dashes people add when typing it.
+pub fn handover_code_matches(submitted: &str, expected: &str) -> bool {
+    let submitted: String = submitted
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+    bool::from(submitted.as_bytes().ct_eq(expected.as_bytes()))
+}
+
+/// Outcome of checking a login password against the stored value.
+#[derive(Debug, PartialEq, Eq)]
+pub enum PasswordMatch {
+    Valid,
+    /// The password matched, but the stored value is a legacy plaintext
+    /// password (or an outdated hash) and should be replaced.
+    NeedsRehash,
+    Invalid,
+}
+
+pub fn hash_password(password: &str) -> Result<String, Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|_| Error::PasswordHash)
+}
+
+pub fn verify_password(password: &str, stored: &str) -> PasswordMatch {
+    match PasswordHash::new(stored) {
+        Ok(hash) => {
+            if Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_err()
+            {
+                return PasswordMatch::Invalid;
+            }
+            if hash.algorithm != argon2::Algorithm::Argon2id.ident() {
+                return PasswordMatch::NeedsRehash;
+            }
+            PasswordMatch::Valid
+        }
+        // Rows created before hashing was introduced store the password as-is.
+        Err(_) => {
+            if bool::from(password.as_bytes().ct_eq(stored.as_bytes())) {
+                PasswordMatch::NeedsRehash
+            } else {
+                PasswordMatch::Invalid
+            }
+        }
+    }
+}
@@ -0,0 +1,140 @@
+//! Fan-out of new chat messages to connected WebSocket clients. The hub is
+//! a trait so the in-process [`LocalHub`] can later be swapped for one
+//! backed by a shared bus when running several server instances.
+
+use crate::model::ChatMessage;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+/// Messages buffered per user before a slow socket starts missing them.
+const CHANNEL_CAPACITY: usize = 64;
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChatEvent {
+    Message {
+        room_id: String,
+        message: ChatMessage,
+    },
+    /// `reader` has read the room up to and including `up_to`.
+    Read {
+        room_id: String,
+        reader: String,
+        up_to: String,
+    },
+}
+
+pub trait ChatHub: Send + Sync {
+    /// Delivers `event` to every connection of each recipient.
+    fn publish(&self, recipients: &[&str], event: ChatEvent);
+
+    /// Events addressed to `username`, for as long as the receiver lives.
+    fn subscribe(&self, username: &str) -> broadcast::Receiver<ChatEvent>;
+
+    /// Forgets the user's channel once none of their sockets listen to it.
+    fn release(&self, username: &str);
+}
+
+/// A user's event stream that hands its channel back to the hub when
+/// dropped, so users who never receive a message don't linger there.
+pub struct Subscription {
+    hub: Arc<dyn ChatHub>,
+    username: String,
+    receiver: Option<broadcast::Receiver<ChatEvent>>,
+}
+
+impl Subscription {
+    pub fn new(hub: Arc<dyn ChatHub>, username: &str) -> Self {
+        let receiver = hub.subscribe(username);
+        Self {
+            hub,
+            username: username.to_string(),
+            receiver: Some(receiver),
+        }
+    }
+
+    pub async fn recv(&mut self) -> Result<ChatEvent, RecvError> {
+        match self.receiver.as_mut() {
+            Some(receiver) => receiver.recv().await,
+            None => Err(RecvError::Closed),
+        }
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        // The receiver has to go first for the hub to see it as unused.
+        self.receiver.take();
+        self.hub.release(&self.username);
+    }
+}
+
+/// Hub for a single server process: one broadcast channel per connected
+/// user, shared by all of that user's sockets.
+#[derive(Default)]
+pub struct LocalHub {
+    channels: Mutex<HashMap<String, broadcast::Sender<ChatEvent>>>,
+}
+
+impl LocalHub {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl ChatHub for LocalHub {
+    fn publish(&self, recipients: &[&str], event: ChatEvent) {
+        let mut channels = self.channels.lock().expect("chat hub lock poisoned");
+        for recipient in recipients {
+            if let Some(sender) = channels.get(*recipient) {
+                if sender.receiver_count() == 0 {
+                    // Everyone disconnected since the last message.
+                    channels.remove(*recipient);
+                } else {
+                    let _ = sender.send(event.clone());
+                }
+            }
+        }
+    }
+
+    fn subscribe(&self, username: &str) -> broadcast::Receiver<ChatEvent> {
+        let mut channels = self.channels.lock().expect("chat hub lock poisoned");
+        channels
+            .entry(username.to_string())
+            .or_insert_with(|| broadcast::channel(CHANNEL_CAPACITY).0)
+            .subscribe()
+    }
+
+    fn release(&self, username: &str) {
+        let mut channels = self.channels.lock().expect("chat hub lock poisoned");
+        if channels.get(username).is_some_and(|sender| sender.receiver_count() == 0) {
+            channels.remove(username);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn channel_count(hub: &LocalHub) -> usize {
+        hub.channels.lock().unwrap().len()
+    }
+
+    #[test]
+    fn dropping_the_last_subscription_frees_the_channel() {
+        let local = Arc::new(LocalHub::new());
+        let hub: Arc<dyn ChatHub> = local.clone();
+
+        let first = Subscription::new(hub.clone(), "alice");
+        let second = Subscription::new(hub.clone(), "alice");
+        assert_eq!(channel_count(&local), 1);
+
+        drop(first);
+        assert_eq!(channel_count(&local), 1);
+        drop(second);
+        assert_eq!(channel_count(&local), 0);
+    }
+}
@@ -0,0 +1,354 @@
+use serde::Deserialize;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+const DEFAULT_CONFIG_FILE: &str = "itshere.toml";
+const MIN_JWT_SECRET_LEN: usize = 32;
+/// Upper bounds on token lifetimes, well past any sensible setting, so
+/// expiry arithmetic can't overflow.
+const MAX_TOKEN_LIFETIME_HOURS: i64 = 24 * 366;
+const MAX_REFRESH_TOKEN_LIFETIME_DAYS: i64 = 366 * 10;
+const DEFAULT_MAX_UPLOAD_BYTES: usize = 5 * 1024 * 1024;
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub auth: AuthConfig,
+    pub database: DatabaseConfig,
+    pub storage: StorageConfig,
+}
+
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    pub listen_addr: SocketAddr,
+    /// Allowed CORS origins; `*` allows any origin.
+    pub cors_origins: Vec<String>,
+    /// Origin clients reach the API on, used to build absolute image URLs.
+    /// Empty means URLs are relative to the API.
+    pub public_url: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct AuthConfig {
+    pub jwt_secret: String,
+    pub token_lifetime_hours: i64,
+    pub refresh_token_lifetime_days: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct DatabaseConfig {
+    pub uri: String,
+    pub name: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct StorageConfig {
+    pub backend: StorageBackend,
+    pub max_upload_bytes: usize,
+}
+
+#[derive(Debug, Clone)]
+pub enum StorageBackend {
+    Local { path: PathBuf },
+    S3(S3Config),
+}
+
+/// Any S3-compatible service, addressed path-style (`endpoint/bucket/key`)
+/// so MinIO and similar stand-ins work without DNS setup.
+#[derive(Debug, Clone)]
+pub struct S3Config {
+    pub endpoint: String,
+    pub bucket: String,
+    pub region: String,
+    pub access_key: String,
+    pub secret_key: String,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Missing { env: &'static str, file_key: &'static str },
+    Invalid { key: &'static str, reason: String },
+    File { path: String, reason: String },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Missing { env, file_key } => write!(
+                f,
+                "missing required setting: set {} or `{}` in the config file",
+                env, file_key
+            ),
+            ConfigError::Invalid { key, reason } => write!(f, "invalid value for {}: {}", key, reason),
+            ConfigError::File { path, reason } => write!(f, "could not read config file {}: {}", path, reason),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Shape of the optional TOML file. Every value can be overridden from the
+/// environment, so everything here is optional.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileConfig {
+    #[serde(default)]
+    server: FileServer,
+    #[serde(default)]
+    auth: FileAuth,
+    #[serde(default)]
+    database: FileDatabase,
+    #[serde(default)]
+    storage: FileStorage,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileServer {
+    listen_addr: Option<String>,
+    cors_origins: Option<Vec<String>>,
+    public_url: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileAuth {
+    jwt_secret: Option<String>,
+    token_lifetime_hours: Option<i64>,
+    refresh_token_lifetime_days: Option<i64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileDatabase {
+    uri: Option<String>,
+    name: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileStorage {
+    backend: Option<String>,
+    path: Option<String>,
+    max_upload_bytes: Option<usize>,
+    #[serde(default)]
+    s3: FileS3,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileS3 {
+    endpoint: Option<String>,
+    bucket: Option<String>,
+    region: Option<String>,
+    access_key: Option<String>,
+    secret_key: Option<String>,
+}
+
+/// Loads the configuration and makes it available through [`config`].
+/// Must be called once at startup, before any handler runs.
+pub fn init() -> Result<&'static Config, ConfigError> {
+    let config = Config::load()?;
+    Ok(CONFIG.get_or_init(|| config))
+}
+
+pub fn config() -> &'static Config {
+    CONFIG.get().expect("config::init must be called at startup")
+}
+
+impl Config {
+    /// Reads `ITSHERE_CONFIG` (or `itshere.toml` when present) and applies
+    /// `ITSHERE_*` environment overrides on top of it.
+    pub fn load() -> Result<Self, ConfigError> {
+        let file = match env_var("ITSHERE_CONFIG") {
+            Some(path) => read_file(Path::new(&path))?,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                read_file(Path::new(DEFAULT_CONFIG_FILE))?
+            }
+            None => FileConfig::default(),
+        };
+
+        let listen_addr = match env_var("ITSHERE_LISTEN_ADDR")
+            .or(file.server.listen_addr)
+        {
+            Some(addr) => parse("ITSHERE_LISTEN_ADDR", &addr)?,
+            // Hosting platforms hand out the port through `PORT`.
+            None => match env_var("PORT") {
+                Some(port) => SocketAddr::from(([0, 0, 0, 0], parse::<u16>("PORT", &port)?)),
+                None => SocketAddr::from(([0, 0, 0, 0], 3000)),
+            },
+        };
+
+        let cors_origins = match env_var("ITSHERE_CORS_ORIGINS") {
+            Some(list) => list
+                .split(',')
+                .map(|origin| origin.trim().to_string())
+                .filter(|origin| !origin.is_empty())
+                .collect(),
+            None => file.server.cors_origins.unwrap_or_else(|| vec!["*".to_string()]),
+        };
+        for origin in &cors_origins {
+            if origin != "*" && axum::http::HeaderValue::from_str(origin).is_err() {
+                return Err(ConfigError::Invalid {
+                    key: "ITSHERE_CORS_ORIGINS",
+                    reason: format!("`{}` is not a valid origin", origin),
+                });
+            }
+        }
+
+        let public_url = env_var("ITSHERE_PUBLIC_URL")
+            .or(file.server.public_url)
+            .unwrap_or_default()
+            .trim_end_matches('/')
+            .to_string();
+
+        let jwt_secret = env_var("ITSHERE_JWT_SECRET")
+            .or(file.auth.jwt_secret)
+            .ok_or(ConfigError::Missing {
+                env: "ITSHERE_JWT_SECRET",
+                file_key: "auth.jwt_secret",
+            })?;
+        if jwt_secret.len() < MIN_JWT_SECRET_LEN {
+            return Err(ConfigError::Invalid {
+                key: "ITSHERE_JWT_SECRET",
+                reason: format!("must be at least {} bytes long", MIN_JWT_SECRET_LEN),
+            });
+        }
+
+        let token_lifetime_hours = match env_var("ITSHERE_TOKEN_LIFETIME_HOURS") {
+            Some(hours) => parse("ITSHERE_TOKEN_LIFETIME_HOURS", &hours)?,
+            None => file.auth.token_lifetime_hours.unwrap_or(24),
+        };
+        if !(1..=MAX_TOKEN_LIFETIME_HOURS).contains(&token_lifetime_hours) {
+            return Err(ConfigError::Invalid {
+                key: "ITSHERE_TOKEN_LIFETIME_HOURS",
+                reason: format!("must be between 1 and {} hours", MAX_TOKEN_LIFETIME_HOURS),
+            });
+        }
+
+        let refresh_token_lifetime_days = match env_var("ITSHERE_REFRESH_TOKEN_LIFETIME_DAYS") {
+            Some(days) => parse("ITSHERE_REFRESH_TOKEN_LIFETIME_DAYS", &days)?,
+            None => file.auth.refresh_token_lifetime_days.unwrap_or(30),
+        };
+        if refresh_token_lifetime_days > MAX_REFRESH_TOKEN_LIFETIME_DAYS {
+            return Err(ConfigError::Invalid {
+                key: "ITSHERE_REFRESH_TOKEN_LIFETIME_DAYS",
+                reason: format!("must be at most {} days", MAX_REFRESH_TOKEN_LIFETIME_DAYS),
+            });
+        }
+        if refresh_token_lifetime_days * 24 <= token_lifetime_hours {
+            return Err(ConfigError::Invalid {
+                key: "ITSHERE_REFRESH_TOKEN_LIFETIME_DAYS",
+                reason: "refresh tokens must outlive access tokens".to_string(),
+            });
+        }
+
+        let database_uri = env_var("ITSHERE_DATABASE_URI")
+            .or(file.database.uri)
+            .ok_or(ConfigError::Missing {
+                env: "ITSHERE_DATABASE_URI",
+                file_key: "database.uri",
+            })?;
+        let database_name = env_var("ITSHERE_DATABASE_NAME")
+            .or(file.database.name)
+            .unwrap_or_else(|| "lost_and_found".to_string());
+
+        let max_upload_bytes = match env_var("ITSHERE_MAX_UPLOAD_BYTES") {
+            Some(bytes) => parse("ITSHERE_MAX_UPLOAD_BYTES", &bytes)?,
+            None => file.storage.max_upload_bytes.unwrap_or(DEFAULT_MAX_UPLOAD_BYTES),
+        };
+        if max_upload_bytes == 0 {
+            return Err(ConfigError::Invalid {
+                key: "ITSHERE_MAX_UPLOAD_BYTES",
+                reason: "must be a positive number of bytes".to_string(),
+            });
+        }
+
+        let storage_backend = env_var("ITSHERE_STORAGE_BACKEND")
+            .or(file.storage.backend)
+            .unwrap_or_else(|| "local".to_string());
+        let backend = match storage_backend.trim() {
+            "local" => StorageBackend::Local {
+                path: env_var("ITSHERE_STORAGE_PATH")
+                    .or(file.storage.path)
+                    .unwrap_or_else(|| "uploads".to_string())
+                    .into(),
+            },
+            "s3" => {
+                let s3 = file.storage.s3;
+                let required = |env: &'static str, file_key: &'static str, value: Option<String>| {
+                    env_var(env)
+                        .or(value)
+                        .ok_or(ConfigError::Missing { env, file_key })
+                };
+                StorageBackend::S3(S3Config {
+                    endpoint: required("ITSHERE_S3_ENDPOINT", "storage.s3.endpoint", s3.endpoint)?
+                        .trim_end_matches('/')
+                        .to_string(),
+                    bucket: required("ITSHERE_S3_BUCKET", "storage.s3.bucket", s3.bucket)?,
+                    region: env_var("ITSHERE_S3_REGION")
+                        .or(s3.region)
+                        .unwrap_or_else(|| "us-east-1".to_string()),
+                    access_key: required("ITSHERE_S3_ACCESS_KEY", "storage.s3.access_key", s3.access_key)?,
+                    secret_key: required("ITSHERE_S3_SECRET_KEY", "storage.s3.secret_key", s3.secret_key)?,
+                })
+            }
+            other => {
+                return Err(ConfigError::Invalid {
+                    key: "ITSHERE_STORAGE_BACKEND",
+                    reason: format!("unknown backend `{}`, expected `local` or `s3`", other),
+                });
+            }
+        };
+
+        Ok(Config {
+            server: ServerConfig {
+                listen_addr,
+                cors_origins,
+                public_url,
+            },
+            auth: AuthConfig {
+                jwt_secret,
+                token_lifetime_hours,
+                refresh_token_lifetime_days,
+            },
+            database: DatabaseConfig {
+                uri: database_uri,
+                name: database_name,
+            },
+            storage: StorageConfig {
+                backend,
+                max_upload_bytes,
+            },
+        })
+    }
+}
+
+fn read_file(path: &Path) -> Result<FileConfig, ConfigError> {
+    let file_error = |reason: String| ConfigError::File {
+        path: path.display().to_string(),
+        reason,
+    };
+    let contents = std::fs::read_to_string(path).map_err(|e| file_error(e.to_string()))?;
+    toml::from_str(&contents).map_err(|e| file_error(e.to_string()))
+}
+
+fn env_var(key: &str) -> Option<String> {
+    std::env::var(key).ok().filter(|value| !value.trim().is_empty())
+}
+
+fn parse<T>(key: &'static str, value: &str) -> Result<T, ConfigError>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    value.trim().parse().map_err(|e: T::Err| ConfigError::Invalid {
+        key,
+        reason: e.to_string(),
+    })
+}
@@ -6,13 +6,27 @@ pub enum Error{
     LoginFail,
     UserWithMailExists,
     TokenCreation,
+    PasswordHash,
     InvalidToken,
     ChatNotFound,
+    MessageNotFound,
     InvalidId,
     Unauthorized,
+    Forbidden,
+    InvalidStatusTransition,
+    InvalidInput(String),
     DatabaseError(String),
     UserNotFound,
     PostNotFound,
+    ClaimNotFound,
+    DuplicateClaim,
+    HandoverNotFound,
+    InvalidHandoverCode,
+    ImageNotFound,
+    LocationNotFound,
+    UnsupportedMediaType,
+    PayloadTooLarge,
+    StorageError(String),
     Custom,
 }
 
@@ -23,9 +37,9 @@ impl From<mongodb::error::Error> for Error {
 }
 
 
-impl IntoResponse for Error {
-    fn into_response(self) -> Response {
-        println!("Error: {:?}", self);
+impl Error {
+    /// Status code and client-facing message for this error.
+    pub fn status_and_message(&self) -> (StatusCode, &str) {
         match self {
             Error::LoginFail => (
                 StatusCode::UNAUTHORIZED,
@@ -36,8 +50,9 @@ impl IntoResponse for Error {
                 "A user with this email already exists",
             ),
             Error::TokenCreation => (StatusCode::INTERNAL_SERVER_ERROR, "Failed to create token"),
+            Error::PasswordHash => (StatusCode::INTERNAL_SERVER_ERROR, "Failed to process password"),
             Error::InvalidToken => (StatusCode::UNAUTHORIZED, "Invalid or expired token"),
-            Error::DatabaseError(msg) => (
+            Error::DatabaseError(_) => (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "Database error"),
             Error::UserNotFound => (
@@ -48,10 +63,50 @@ impl IntoResponse for Error {
                 StatusCode::CONFLICT,
                 "Post is not found"
             ),
+            Error::ClaimNotFound => (
+                StatusCode::NOT_FOUND,
+                "Claim is not found"
+            ),
+            Error::DuplicateClaim => (
+                StatusCode::CONFLICT,
+                "You already have a pending claim on this post"
+            ),
+            Error::HandoverNotFound => (
+                StatusCode::NOT_FOUND,
+                "Handover is not found"
+            ),
+            Error::InvalidHandoverCode => (
+                StatusCode::BAD_REQUEST,
+                "Handover code is invalid"
+            ),
+            Error::ImageNotFound => (
+                StatusCode::NOT_FOUND,
+                "Image is not found"
+            ),
+            Error::LocationNotFound => (
+                StatusCode::NOT_FOUND,
+                "Location is not found"
+            ),
+            Error::UnsupportedMediaType => (
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "Only JPEG, PNG, WebP and GIF images are accepted"
+            ),
+            Error::PayloadTooLarge => (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "The file is too large"
+            ),
+            Error::StorageError(_) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Storage error"
+            ),
             Error::ChatNotFound => (
                 StatusCode::CONFLICT,
                 "Chat is not found"
             ),
+            Error::MessageNotFound => (
+                StatusCode::NOT_FOUND,
+                "Message is not found"
+            ),
             Error::InvalidId => (
                 StatusCode::BAD_REQUEST,
                 "Invalid ID format"
@@ -60,12 +115,27 @@ impl IntoResponse for Error {
                 StatusCode::UNAUTHORIZED,
                 "Unauthorized access",
             ),
+            Error::Forbidden => (
+                StatusCode::FORBIDDEN,
+                "You are not allowed to do this",
+            ),
+            Error::InvalidStatusTransition => (
+                StatusCode::CONFLICT,
+                "This status change is not allowed",
+            ),
+            Error::InvalidInput(message) => (StatusCode::BAD_REQUEST, message),
             Error::Custom => (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "An unexpected error occurred",
             ),
-            
         }
-        .into_response()
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        println!("Error: {:?}", self);
+        let (status, message) = self.status_and_message();
+        (status, message.to_string()).into_response()
     }
 }
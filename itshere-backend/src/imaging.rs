@@ -0,0 +1,274 @@
+//! Normalising uploaded photos: phone pictures arrive rotated through EXIF,
+//! carry GPS metadata and weigh several megabytes. Every upload is decoded,
+//! turned upright and re-encoded, which drops all metadata, and gets
+//! smaller variants for the feed and the post page.
+//!
+//! Everything is written as JPEG: the `image` crate only encodes lossless
+//! WebP, which comes out larger than a JPEG for photos.
+
+use crate::{Error, Result};
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageDecoder, ImageReader, Limits, Rgb, RgbImage};
+use serde::{Deserialize, Serialize};
+use std::io::Cursor;
+
+/// Longest side of the stored original.
+const FULL_MAX_SIDE: u32 = 2048;
+const DETAIL_MAX_SIDE: u32 = 1080;
+/// Feed thumbnails are square crops so the grid lines up.
+const FEED_SIDE: u32 = 400;
+const JPEG_QUALITY: u8 = 82;
+/// Hashes at most this many bits apart are treated as the same photo.
+pub const DUPLICATE_MAX_DISTANCE: u32 = 8;
+/// Unrelated photos differ in about half of the 64 bits, so similarity
+/// reaches zero there.
+const UNRELATED_DISTANCE: u32 = 32;
+/// Refuse images that would decode to absurd sizes (decompression bombs).
+const MAX_DECODED_SIDE: u32 = 12_000;
+const MAX_DECODE_ALLOC: u64 = 512 * 1024 * 1024;
+
+pub const CONTENT_TYPE: &str = "image/jpeg";
+pub const EXTENSION: &str = "jpg";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageSize {
+    Full,
+    Detail,
+    Feed,
+}
+
+impl ImageSize {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ImageSize::Full => "full",
+            ImageSize::Detail => "detail",
+            ImageSize::Feed => "feed",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        [ImageSize::Full, ImageSize::Detail, ImageSize::Feed]
+            .into_iter()
+            .find(|size| size.as_str() == value)
+    }
+}
+
+/// JPEG encodings of an upload, all without metadata.
+pub struct ProcessedImage {
+    pub full: Vec<u8>,
+    pub detail: Vec<u8>,
+    pub feed: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+    /// Perceptual hash, see [`dhash`].
+    pub hash: u64,
+}
+
+/// Decodes `bytes`, applies the EXIF orientation and re-encodes every
+/// size. CPU heavy, so call it from a blocking task.
+pub fn process(bytes: &[u8]) -> Result<ProcessedImage> {
+    let image = decode(bytes)?;
+    let full = fit(&image, FULL_MAX_SIDE);
+    let detail = fit(&full, DETAIL_MAX_SIDE);
+    let feed = full.resize_to_fill(FEED_SIDE, FEED_SIDE, FilterType::Lanczos3);
+
+    Ok(ProcessedImage {
+        hash: dhash(&full),
+        width: full.width(),
+        height: full.height(),
+        full: encode(&full)?,
+        detail: encode(&detail)?,
+        feed: encode(&feed)?,
+    })
+}
+
+/// Difference hash: each bit says whether a pixel of a 9x8 grayscale
+/// thumbnail is brighter than its right neighbour. Survives re-encoding,
+/// resizing and small edits, so reposts of a photo hash (nearly) the same.
+pub fn dhash(image: &DynamicImage) -> u64 {
+    let small = image
+        .resize_exact(9, 8, FilterType::Triangle)
+        .to_luma8();
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            let brighter = small.get_pixel(x, y).0[0] > small.get_pixel(x + 1, y).0[0];
+            hash = (hash << 1) | brighter as u64;
+        }
+    }
+    hash
+}
+
+/// Hashes are stored as hex strings; BSON has no unsigned 64-bit integer.
+pub fn hash_to_hex(hash: u64) -> String {
+    format!("{:016x}", hash)
+}
+
+fn distance(a: &str, b: &str) -> Option<u32> {
+    let a = u64::from_str_radix(a, 16).ok()?;
+    let b = u64::from_str_radix(b, 16).ok()?;
+    Some((a ^ b).count_ones())
+}
+
+pub fn is_near_duplicate(a: &str, b: &str) -> bool {
+    distance(a, b).is_some_and(|distance| distance <= DUPLICATE_MAX_DISTANCE)
+}
+
+/// 1 for the same photo, falling to 0 for unrelated ones.
+pub fn similarity(a: &str, b: &str) -> Option<f64> {
+    let distance = distance(a, b)?.min(UNRELATED_DISTANCE);
+    Some(1.0 - distance as f64 / UNRELATED_DISTANCE as f64)
+}
+
+fn decode(bytes: &[u8]) -> Result<DynamicImage> {
+    let unreadable = |_| Error::InvalidInput("The image could not be read".to_string());
+
+    let mut reader = ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()
+        .map_err(|_| Error::UnsupportedMediaType)?;
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_DECODED_SIDE);
+    limits.max_image_height = Some(MAX_DECODED_SIDE);
+    limits.max_alloc = Some(MAX_DECODE_ALLOC);
+    reader.limits(limits);
+
+    let mut decoder = reader.into_decoder().map_err(unreadable)?;
+    let orientation = decoder.orientation().map_err(unreadable)?;
+    let mut image = DynamicImage::from_decoder(decoder).map_err(unreadable)?;
+    image.apply_orientation(orientation);
+    Ok(image)
+}
+
+/// Shrinks `image` to fit in a `max_side` square; never enlarges it.
+fn fit(image: &DynamicImage, max_side: u32) -> DynamicImage {
+    if image.width() <= max_side && image.height() <= max_side {
+        image.clone()
+    } else {
+        image.resize(max_side, max_side, FilterType::Lanczos3)
+    }
+}
+
+fn encode(image: &DynamicImage) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    JpegEncoder::new_with_quality(&mut bytes, JPEG_QUALITY)
+        .encode_image(&flatten(image))
+        .map_err(|e| Error::InvalidInput(format!("The image could not be encoded: {}", e)))?;
+    Ok(bytes)
+}
+
+/// JPEG has no alpha channel; put transparent images on a white background
+/// instead of letting transparent pixels turn black.
+fn flatten(image: &DynamicImage) -> RgbImage {
+    if !image.color().has_alpha() {
+        return image.to_rgb8();
+    }
+    let rgba = image.to_rgba8();
+    RgbImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+        let [r, g, b, a] = rgba.get_pixel(x, y).0;
+        let blend = |channel: u8| {
+            ((channel as u32 * a as u32 + 255 * (255 - a as u32)) / 255) as u8
+        };
+        Rgb([blend(r), blend(g), blend(b)])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageFormat, Rgba, RgbaImage};
+
+    /// A photo-like test image: diagonal gradients with a dark block.
+    fn sample(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+            let in_block = x > width / 3 && x < width / 2 && y > height / 4 && y < height / 2;
+            let v = ((x * 255 / width + y * 128 / height) % 256) as u8;
+            if in_block { Rgb([20, 20, 20]) } else { Rgb([v, 255 - v, v / 2]) }
+        }))
+    }
+
+    fn png(image: &DynamicImage) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        image.write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png).unwrap();
+        bytes
+    }
+
+    fn dimensions(jpeg: &[u8]) -> (u32, u32) {
+        let image = image::load_from_memory_with_format(jpeg, ImageFormat::Jpeg).unwrap();
+        (image.width(), image.height())
+    }
+
+    #[test]
+    fn dhash_compares_each_pixel_with_its_right_neighbour() {
+        let falling = DynamicImage::ImageLuma8(image::GrayImage::from_fn(90, 80, |x, _| {
+            image::Luma([255 - (x * 2) as u8])
+        }));
+        assert_eq!(dhash(&falling), u64::MAX);
+        assert_eq!(dhash(&falling.fliph()), 0);
+    }
+
+    #[test]
+    fn resized_and_reencoded_copies_stay_near_duplicates() {
+        let original = sample(800, 600);
+        let processed = process(&png(&original.resize(400, 300, FilterType::Triangle))).unwrap();
+        let a = hash_to_hex(dhash(&original));
+        let b = hash_to_hex(processed.hash);
+        assert!(is_near_duplicate(&a, &b), "{a} vs {b}");
+
+        let other = hash_to_hex(dhash(&original.fliph()));
+        assert!(!is_near_duplicate(&a, &other));
+    }
+
+    #[test]
+    fn similarity_falls_with_hash_distance() {
+        assert_eq!(hash_to_hex(0xff), "00000000000000ff");
+        let zero = hash_to_hex(0);
+        assert_eq!(similarity(&zero, &zero), Some(1.0));
+        assert_eq!(similarity(&zero, &hash_to_hex(0xff)), Some(0.75));
+        assert_eq!(similarity(&zero, &hash_to_hex(u64::MAX)), Some(0.0));
+        assert_eq!(similarity(&zero, "not hex"), None);
+
+        assert!(is_near_duplicate(&zero, &hash_to_hex(0xff)));
+        assert!(!is_near_duplicate(&zero, &hash_to_hex(0x1ff)));
+    }
+
+    #[test]
+    fn process_produces_every_size() {
+        let processed = process(&png(&sample(1200, 600))).unwrap();
+        assert_eq!((processed.width, processed.height), (1200, 600));
+        assert_eq!(dimensions(&processed.full), (1200, 600));
+        assert_eq!(dimensions(&processed.detail), (1080, 540));
+        assert_eq!(dimensions(&processed.feed), (FEED_SIDE, FEED_SIDE));
+
+        // Small images are never enlarged.
+        let processed = process(&png(&sample(300, 200))).unwrap();
+        assert_eq!(dimensions(&processed.full), (300, 200));
+        assert_eq!(dimensions(&processed.detail), (300, 200));
+    }
+
+    #[test]
+    fn fit_caps_the_longest_side() {
+        let shrunk = fit(&sample(2400, 1200), FULL_MAX_SIDE);
+        assert_eq!((shrunk.width(), shrunk.height()), (2048, 1024));
+    }
+
+    #[test]
+    fn transparent_pixels_become_white() {
+        let clear = DynamicImage::ImageRgba8(RgbaImage::from_pixel(4, 4, Rgba([0, 0, 0, 0])));
+        assert!(flatten(&clear).pixels().all(|pixel| pixel.0 == [255, 255, 255]));
+    }
+
+    #[test]
+    fn unreadable_bytes_are_rejected() {
+        assert!(process(b"definitely not an image").is_err());
+    }
+
+    #[test]
+    fn image_sizes_parse_their_own_names() {
+        for size in [ImageSize::Full, ImageSize::Detail, ImageSize::Feed] {
+            assert_eq!(ImageSize::parse(size.as_str()), Some(size));
+        }
+        assert_eq!(ImageSize::parse("huge"), None);
+    }
+}
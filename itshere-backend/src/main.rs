@@ -1,26 +1,81 @@
 pub use self::error::{Error, Result};
-use axum::{Router, middleware, response::Response};
+use axum::Router;
 use db::connect_to_db;
 use model::ModelController;
-use std::net::SocketAddr;
 use std::sync::Arc;
 use tower_cookies::CookieManagerLayer;
-use tracing_subscriber::layer;
-use tower_http::cors::{Any, CorsLayer};
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
 mod auth;
+mod chat_hub;
+mod config;
 mod db;
 mod error;
+mod imaging;
+mod matching;
+mod migrations;
 mod model;
+mod search;
+mod storage;
 mod web;
 #[tokio::main]
 async fn main() {
     tracing_subscriber::fmt::init(); // Optional logging
 
-    let db = connect_to_db().await.unwrap();
-    let controller = Arc::new(ModelController::new(db));
+    let config = match config::init() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Configuration error: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let db = connect_to_db(&config.database).await.unwrap();
+    let storage = storage::from_config(&config.storage);
+    let hub = Arc::new(chat_hub::LocalHub::new());
+    let controller = Arc::new(ModelController::new(db, storage, hub));
+
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("migrate") {
+        if let Err(e) = migrations::cli(&controller, args.get(2).map(String::as_str)).await {
+            eprintln!("Migration failed: {:?}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    match migrations::check(&controller).await.unwrap() {
+        migrations::SchemaState::UpToDate => {}
+        migrations::SchemaState::Behind(pending) => {
+            eprintln!(
+                "Database schema is behind: {} pending migration(s). Run `itshere-backend migrate run` first.",
+                pending.len()
+            );
+            std::process::exit(1);
+        }
+        migrations::SchemaState::Ahead(unknown) => {
+            eprintln!(
+                "Database has migrations {:?} this build does not know; refusing to start an older version.",
+                unknown
+            );
+            std::process::exit(1);
+        }
+    }
+    controller.init_indexes().await.unwrap();
+
+    let allow_origin = if config.server.cors_origins.iter().any(|origin| origin == "*") {
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(
+            config
+                .server
+                .cors_origins
+                .iter()
+                .map(|origin| origin.parse().expect("origins are validated on load")),
+        )
+    };
 
     let cors = CorsLayer::new()
-        .allow_origin(Any)
+        .allow_origin(allow_origin)
         .allow_methods([
             axum::http::Method::GET,
             axum::http::Method::POST,
@@ -36,11 +91,13 @@ async fn main() {
         .merge(web::routes_post::routes(controller.clone()))
         .merge(web::route_edit::routes(controller.clone()))
         .merge(web::routes_chat::routes(controller.clone()))
+        .merge(web::routes_claim::routes(controller.clone()))
+        .merge(web::routes_upload::routes(controller.clone()))
+        .merge(web::routes_location::routes(controller.clone()))
         .layer(cors)
         .layer(CookieManagerLayer::new());
 
-    // ✅ Hardcoded address for deployment
-    let addr = SocketAddr::from(([0, 0, 0, 0], 3000));
+    let addr = config.server.listen_addr;
     println!("Server running at http://{}", addr);
 
     axum::Server::bind(&addr)
@@ -0,0 +1,287 @@
+//! Lost-to-found matching. Whenever a post is created or edited it is
+//! scored against open posts of the opposite kind, and candidates above
+//! [`MATCH_THRESHOLD`] are stored in the `matches` collection so both the
+//! owner and the finder can see likely hits.
+
+use crate::{
+    Result,
+    imaging,
+    model::{Category, ItemAttributes, MissingPost, ModelController, PostKind, ScoreBreakdown},
+    search::stem,
+};
+use chrono::{DateTime, Utc};
+use mongodb::bson::oid::ObjectId;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+pub const MATCH_THRESHOLD: f64 = 0.35;
+/// Posts further apart than this many days get no date credit.
+pub const DATE_WINDOW_DAYS: i64 = 30;
+
+const CATEGORY_WEIGHT: f64 = 0.2;
+const TEXT_WEIGHT: f64 = 0.35;
+const ATTRIBUTE_WEIGHT: f64 = 0.15;
+const PLACE_WEIGHT: f64 = 0.15;
+const DATE_WEIGHT: f64 = 0.15;
+/// Share of the total taken by photo similarity when both posts have one;
+/// the other signals are scaled down to make room.
+const IMAGE_WEIGHT: f64 = 0.2;
+
+/// Score given to a signal that cannot be compared, e.g. attributes
+/// missing on one side, so missing data neither helps nor sinks a match.
+const NEUTRAL: f64 = 0.5;
+
+const STOPWORDS: [&str; 12] = [
+    "the", "and", "with", "near", "for", "from", "has", "have", "lost", "found", "was", "its",
+];
+
+impl ScoreBreakdown {
+    pub fn total(&self) -> f64 {
+        let base = self.category * CATEGORY_WEIGHT
+            + self.text * TEXT_WEIGHT
+            + self.attributes * ATTRIBUTE_WEIGHT
+            + self.place * PLACE_WEIGHT
+            + self.date * DATE_WEIGHT;
+        match self.image {
+            Some(image) => base * (1.0 - IMAGE_WEIGHT) + image * IMAGE_WEIGHT,
+            None => base,
+        }
+    }
+}
+
+/// Scores how likely `a` and `b` describe the same item. Returns `None`
+/// when they cannot be a pair at all: same kind, or categories or serial
+/// numbers that contradict each other.
+pub fn score(a: &MissingPost, b: &MissingPost) -> Option<ScoreBreakdown> {
+    if a.kind == b.kind {
+        return None;
+    }
+    let (lost, found) = if a.kind == PostKind::Lost { (a, b) } else { (b, a) };
+
+    Some(ScoreBreakdown {
+        category: category_score(lost.category, found.category)?,
+        text: jaccard(&tokens(&lost.description), &tokens(&found.description)),
+        attributes: attribute_score(&lost.attributes, &found.attributes)?,
+        place: place_score(lost, found),
+        date: date_score(&lost.date, &found.date),
+        image: match (&lost.image_hash, &found.image_hash) {
+            (Some(lost), Some(found)) => imaging::similarity(lost, found),
+            _ => None,
+        },
+    })
+}
+
+fn category_score(lost: Category, found: Category) -> Option<f64> {
+    if lost == found {
+        Some(1.0)
+    } else if lost == Category::Other || found == Category::Other {
+        Some(NEUTRAL)
+    } else {
+        None
+    }
+}
+
+fn attribute_score(lost: &ItemAttributes, found: &ItemAttributes) -> Option<f64> {
+    if let (Some(lost), Some(found)) = (&lost.serial_last4, &found.serial_last4) {
+        return (lost == found).then_some(1.0);
+    }
+
+    let pairs = [
+        (&lost.color, &found.color),
+        (&lost.brand, &found.brand),
+    ];
+    let compared: Vec<f64> = pairs
+        .into_iter()
+        .filter_map(|pair| match pair {
+            (Some(lost), Some(found)) => Some(if lost.eq_ignore_ascii_case(found) { 1.0 } else { 0.0 }),
+            _ => None,
+        })
+        .collect();
+
+    if compared.is_empty() {
+        Some(NEUTRAL)
+    } else {
+        Some(compared.iter().sum::<f64>() / compared.len() as f64)
+    }
+}
+
+/// Posts pinned to the same directory location get full credit; anything
+/// else falls back to comparing the free-text place.
+fn place_score(lost: &MissingPost, found: &MissingPost) -> f64 {
+    if lost.location_id.is_some() && lost.location_id == found.location_id {
+        return 1.0;
+    }
+    jaccard(&tokens(&lost.place), &tokens(&found.place))
+}
+
+/// Full credit on the same day, falling linearly to zero at the window's
+/// edge. An item cannot be found before it was lost, beyond a day's slack
+/// for time zones and sloppy dates.
+fn date_score(lost: &DateTime<Utc>, found: &DateTime<Utc>) -> f64 {
+    let days = (found.date_naive() - lost.date_naive()).num_days();
+    if days < -1 {
+        return 0.0;
+    }
+    (1.0 - days.abs() as f64 / DATE_WINDOW_DAYS as f64).max(0.0)
+}
+
+fn tokens(text: &str) -> HashSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .map(str::to_lowercase)
+        .filter(|word| word.chars().count() >= 3 && !STOPWORDS.contains(&word.as_str()))
+        .map(|word| stem(&word).to_string())
+        .collect()
+}
+
+fn jaccard(a: &HashSet<String>, b: &HashSet<String>) -> f64 {
+    if a.is_empty() || b.is_empty() {
+        return 0.0;
+    }
+    a.intersection(b).count() as f64 / a.union(b).count() as f64
+}
+
+/// Recomputes and stores the matches of one post.
+pub async fn refresh_matches(controller: &ModelController, post_id: ObjectId) -> Result<usize> {
+    let post = controller.find_live_post(&post_id.to_hex()).await?;
+    let candidates = controller.match_candidates(&post).await?;
+
+    let scored: Vec<(ObjectId, ScoreBreakdown)> = candidates
+        .iter()
+        .filter_map(|candidate| {
+            let breakdown = score(&post, candidate)?;
+            (breakdown.total() >= MATCH_THRESHOLD).then_some((candidate.id?, breakdown))
+        })
+        .collect();
+
+    let count = scored.len();
+    controller.save_matches(&post, scored).await?;
+    Ok(count)
+}
+
+/// Runs [`refresh_matches`] in the background so posting stays fast.
+pub fn spawn_refresh(controller: Arc<ModelController>, post_id: ObjectId) {
+    tokio::spawn(async move {
+        if let Err(e) = refresh_matches(&controller, post_id).await {
+            eprintln!("Error refreshing matches for post {}: {:?}", post_id, e);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::timestamp;
+    use mongodb::bson::{doc, from_document};
+
+    fn post(kind: PostKind, description: &str, place: &str, date: &str) -> MissingPost {
+        let kind = if kind == PostKind::Lost { "lost" } else { "found" };
+        from_document(doc! {
+            "kind": kind,
+            "description": description,
+            "date": timestamp::to_bson(&timestamp::parse(date).unwrap()),
+            "place": place,
+            "image_link": "",
+            "user": "alice",
+            "comments": [],
+        })
+        .unwrap()
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn posts_of_the_same_kind_never_match() {
+        let a = post(PostKind::Lost, "blue bottle", "library", "2024-05-01");
+        let b = post(PostKind::Lost, "blue bottle", "library", "2024-05-01");
+        assert!(score(&a, &b).is_none());
+    }
+
+    #[test]
+    fn scores_each_signal() {
+        let mut lost = post(PostKind::Lost, "Lost my blue Hydro Flask bottle", "Main library", "2024-05-01");
+        let mut found = post(PostKind::Found, "Found blue flask bottle near the gym", "Library entrance", "2024-05-16");
+        lost.category = Category::Bottles;
+        found.category = Category::Bottles;
+
+        // Order of the arguments doesn't matter.
+        let breakdown = score(&found, &lost).unwrap();
+        assert!(close(breakdown.category, 1.0));
+        // {blue, hydro, flask, bottle} vs {blue, flask, bottle, gym}
+        assert!(close(breakdown.text, 3.0 / 5.0));
+        assert!(close(breakdown.attributes, NEUTRAL));
+        // {main, library} vs {library, entrance}
+        assert!(close(breakdown.place, 1.0 / 3.0));
+        assert!(close(breakdown.date, 0.5));
+        assert!(breakdown.image.is_none());
+        assert!(breakdown.total() > MATCH_THRESHOLD);
+    }
+
+    #[test]
+    fn contradicting_category_or_serial_rules_a_pair_out() {
+        let mut lost = post(PostKind::Lost, "wallet", "gym", "2024-05-01");
+        let mut found = post(PostKind::Found, "wallet", "gym", "2024-05-01");
+        lost.category = Category::Keys;
+        found.category = Category::Bags;
+        assert!(score(&lost, &found).is_none());
+
+        found.category = Category::Other;
+        assert!(close(score(&lost, &found).unwrap().category, NEUTRAL));
+
+        lost.attributes.serial_last4 = Some("1234".to_string());
+        found.attributes.serial_last4 = Some("9999".to_string());
+        assert!(score(&lost, &found).is_none());
+        found.attributes.serial_last4 = Some("1234".to_string());
+        assert!(close(score(&lost, &found).unwrap().attributes, 1.0));
+    }
+
+    #[test]
+    fn attributes_score_the_share_that_agree() {
+        let mut lost = post(PostKind::Lost, "", "", "2024-05-01");
+        let mut found = post(PostKind::Found, "", "", "2024-05-01");
+        lost.attributes.color = Some("black".to_string());
+        lost.attributes.brand = Some("Sony".to_string());
+        found.attributes.color = Some("Black".to_string());
+        found.attributes.brand = Some("Bose".to_string());
+        assert!(close(score(&lost, &found).unwrap().attributes, 0.5));
+    }
+
+    #[test]
+    fn shared_directory_location_gets_full_place_credit() {
+        let mut lost = post(PostKind::Lost, "", "2nd floor", "2024-05-01");
+        let mut found = post(PostKind::Found, "", "by the printers", "2024-05-01");
+        assert!(close(score(&lost, &found).unwrap().place, 0.0));
+
+        let location = ObjectId::new();
+        lost.location_id = Some(location);
+        found.location_id = Some(location);
+        assert!(close(score(&lost, &found).unwrap().place, 1.0));
+    }
+
+    #[test]
+    fn items_cannot_be_found_before_they_were_lost() {
+        let lost = post(PostKind::Lost, "", "", "2024-05-10T23:00:00Z");
+        let date = |found: &str| {
+            score(&lost, &post(PostKind::Found, "", "", found)).unwrap().date
+        };
+        assert!(close(date("2024-05-10T08:00:00Z"), 1.0));
+        // A day of slack for time zones.
+        assert!(close(date("2024-05-09"), 1.0 - 1.0 / DATE_WINDOW_DAYS as f64));
+        assert!(close(date("2024-05-08"), 0.0));
+        assert!(close(date("2024-07-01"), 0.0));
+    }
+
+    #[test]
+    fn photo_similarity_takes_a_fifth_of_the_total() {
+        let mut lost = post(PostKind::Lost, "black umbrella", "gym", "2024-05-01");
+        let mut found = post(PostKind::Found, "black umbrella", "gym", "2024-05-01");
+        let base = score(&lost, &found).unwrap().total();
+
+        lost.image_hash = Some(imaging::hash_to_hex(0));
+        found.image_hash = Some(imaging::hash_to_hex(0xff));
+        let breakdown = score(&lost, &found).unwrap();
+        assert_eq!(breakdown.image, Some(0.75));
+        assert!(close(breakdown.total(), base * 0.8 + 0.75 * 0.2));
+    }
+}
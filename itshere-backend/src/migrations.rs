@@ -0,0 +1,248 @@
+//! Versioned schema migrations. Each step upgrades existing documents to
+//! what the current models expect; applied steps are recorded in the
+//! `migrations` collection. Run them with `itshere-backend migrate run`;
+//! the server refuses to start while any are pending. A runner claims a
+//! version by inserting its record before running it, so concurrent
+//! runners never apply the same step twice.
+
+use crate::{Error, Result, model::ModelController, model::is_duplicate_key, model::timestamp};
+use chrono::Utc;
+use futures::TryStreamExt;
+use futures::future::BoxFuture;
+use mongodb::bson::{Document, doc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+type Up = for<'a> fn(&'a ModelController) -> BoxFuture<'a, Result<()>>;
+
+pub struct Migration {
+    /// Applied in increasing order; never reuse or renumber a version.
+    pub version: u32,
+    pub name: &'static str,
+    up: Up,
+}
+
+/// Every migration, oldest first. Append new ones at the end.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "string_dates_to_datetimes",
+        up: |controller| {
+            Box::pin(async move { controller.migrate_string_dates().await.map(|_| ()) })
+        },
+    },
+    Migration {
+        version: 2,
+        name: "backfill_post_defaults",
+        up: |controller| Box::pin(backfill_post_defaults(controller)),
+    },
+];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MigrationState {
+    /// Claimed by a runner that is still applying it, or died doing so.
+    Started,
+    /// Records written before runners claimed versions are all done.
+    #[default]
+    Done,
+}
+
+/// Record of a claimed or applied migration, keyed by version.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AppliedMigration {
+    #[serde(rename = "_id")]
+    pub version: u32,
+    pub name: String,
+    #[serde(default)]
+    pub state: MigrationState,
+    /// When the migration was started, then when it finished.
+    #[serde(with = "timestamp")]
+    pub applied_at: chrono::DateTime<Utc>,
+}
+
+pub enum SchemaState {
+    UpToDate,
+    Behind(Vec<&'static Migration>),
+    /// The database has migrations this build doesn't know, i.e. it was
+    /// migrated by a newer version.
+    Ahead(Vec<u32>),
+}
+
+async fn applied(controller: &ModelController) -> Result<Vec<AppliedMigration>> {
+    let cursor = controller
+        .migration_collection
+        .find(doc! {}, None)
+        .await?;
+    Ok(cursor.try_collect().await?)
+}
+
+pub async fn check(controller: &ModelController) -> Result<SchemaState> {
+    let records = applied(controller).await?;
+    let mut unknown: Vec<u32> = records
+        .iter()
+        .map(|migration| migration.version)
+        .filter(|version| !MIGRATIONS.iter().any(|m| m.version == *version))
+        .collect();
+    if !unknown.is_empty() {
+        unknown.sort_unstable();
+        return Ok(SchemaState::Ahead(unknown));
+    }
+
+    // Unfinished migrations are still pending.
+    let applied: HashSet<u32> = records
+        .iter()
+        .filter(|migration| migration.state == MigrationState::Done)
+        .map(|migration| migration.version)
+        .collect();
+    let pending: Vec<&Migration> = MIGRATIONS
+        .iter()
+        .filter(|migration| !applied.contains(&migration.version))
+        .collect();
+    if pending.is_empty() {
+        Ok(SchemaState::UpToDate)
+    } else {
+        Ok(SchemaState::Behind(pending))
+    }
+}
+
+/// Applies every pending migration in order, stopping at the first failure.
+pub async fn run(controller: &ModelController) -> Result<Vec<&'static Migration>> {
+    let pending = match check(controller).await? {
+        SchemaState::UpToDate => return Ok(vec![]),
+        SchemaState::Behind(pending) => pending,
+        SchemaState::Ahead(unknown) => {
+            return Err(Error::DatabaseError(format!(
+                "database has migrations {:?} unknown to this build",
+                unknown
+            )));
+        }
+    };
+
+    for migration in &pending {
+        claim(controller, migration).await?;
+        println!("Applying migration {} {}", migration.version, migration.name);
+        let record = doc! { "_id": migration.version, "state": "started" };
+        if let Err(e) = (migration.up)(controller).await {
+            // Release the claim so the migration can be retried.
+            controller.migration_collection.delete_one(record, None).await?;
+            return Err(e);
+        }
+        controller
+            .migration_collection
+            .update_one(
+                record,
+                doc! {
+                    "$set": {
+                        "state": "done",
+                        "applied_at": timestamp::to_bson(&Utc::now()),
+                    }
+                },
+                None,
+            )
+            .await?;
+    }
+    Ok(pending)
+}
+
+/// Records that this runner is applying `migration`. The version is the
+/// record's `_id`, so a second runner's insert fails and it stops before
+/// touching any data.
+async fn claim(controller: &ModelController, migration: &Migration) -> Result<()> {
+    let record = AppliedMigration {
+        version: migration.version,
+        name: migration.name.to_string(),
+        state: MigrationState::Started,
+        applied_at: Utc::now(),
+    };
+    match controller.migration_collection.insert_one(record, None).await {
+        Ok(_) => Ok(()),
+        Err(e) if is_duplicate_key(&e) => Err(Error::DatabaseError(format!(
+            "migration {} was started by another runner; if none is running, \
+             delete its record from the migrations collection and retry",
+            migration.version
+        ))),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Entry point of `itshere-backend migrate <run|list>`.
+pub async fn cli(controller: &ModelController, command: Option<&str>) -> Result<()> {
+    match command {
+        Some("run") => {
+            let applied = run(controller).await?;
+            if applied.is_empty() {
+                println!("Schema is up to date");
+            } else {
+                println!("Applied {} migration(s)", applied.len());
+            }
+        }
+        Some("list") => {
+            let applied = applied(controller).await?;
+            for migration in MIGRATIONS {
+                let state = match applied.iter().find(|a| a.version == migration.version) {
+                    Some(applied) if applied.state == MigrationState::Started => {
+                        format!("started {}, not finished", applied.applied_at.to_rfc3339())
+                    }
+                    Some(applied) => format!("applied {}", applied.applied_at.to_rfc3339()),
+                    None => "pending".to_string(),
+                };
+                println!("{:>4}  {:<32} {}", migration.version, migration.name, state);
+            }
+            for unknown in applied
+                .iter()
+                .filter(|a| !MIGRATIONS.iter().any(|m| m.version == a.version))
+            {
+                println!("{:>4}  {:<32} unknown to this build", unknown.version, unknown.name);
+            }
+        }
+        _ => {
+            return Err(Error::InvalidInput(
+                "usage: itshere-backend migrate <run|list>".to_string(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Writes the defaults older posts were read with (`kind`, `category`,
+/// `status` and the list fields), so queries no longer have to treat a
+/// missing field as the default.
+async fn backfill_post_defaults(controller: &ModelController) -> Result<()> {
+    let posts = controller.post_collection.clone_with_type::<Document>();
+    let defaults = [
+        ("kind", "lost".into()),
+        ("category", "other".into()),
+        ("status", "open".into()),
+        ("comments", mongodb::bson::Bson::Array(vec![])),
+        ("status_history", mongodb::bson::Bson::Array(vec![])),
+    ];
+    for (field, value) in defaults {
+        posts
+            .update_many(
+                doc! { field: { "$exists": false } },
+                doc! { "$set": { field: value } },
+                None,
+            )
+            .await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mongodb::bson::{DateTime, from_document};
+
+    #[test]
+    fn records_from_before_claims_count_as_done() {
+        let legacy = doc! { "_id": 1, "name": "string_dates_to_datetimes", "applied_at": DateTime::now() };
+        let record: AppliedMigration = from_document(legacy).unwrap();
+        assert_eq!(record.state, MigrationState::Done);
+    }
+
+    #[test]
+    fn versions_are_unique_and_increasing() {
+        assert!(MIGRATIONS.windows(2).all(|pair| pair[0].version < pair[1].version));
+    }
+}
@@ -1,15 +1,104 @@
+use crate::auth::{
+    generate_handover_code, generate_refresh_token, handover_code_matches, hash_password,
+    hash_refresh_token,
+};
+use crate::chat_hub::{ChatEvent, ChatHub};
+use crate::config::config;
+use crate::imaging::{self, ImageSize};
+use crate::migrations::AppliedMigration;
+use crate::storage::{ImageFormat, Storage, StoredObject};
 use crate::{error::Error, error::Result};
 use futures::stream::TryStreamExt;
 use mongodb::bson::oid::ObjectId;
-use mongodb::bson::{to_bson, uuid};
-use mongodb::options::FindOptions;
-use mongodb::{Collection, bson::doc};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use mongodb::bson::{Bson, DateTime, Document, from_document, to_bson, uuid};
+use mongodb::error::{ErrorKind, WriteFailure};
+use mongodb::options::{FindOptions, IndexOptions, UpdateOptions};
+use std::collections::HashMap;
+use std::sync::Arc;
+use mongodb::{Collection, IndexModel, bson::doc};
+use std::time::Duration;
+use chrono::Utc;
 use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Debug, Serialize)]
-pub struct User {
-    pub id: u64,
-    pub username: String,
+/// Serde adapter for `chrono::DateTime<Utc>` fields: stored in Mongo as a
+/// BSON datetime, rendered as RFC 3339 in JSON. Both forms are accepted
+/// when reading, along with the other ISO-8601 forms of [`timestamp::parse`],
+/// so documents still holding strings keep loading.
+///
+/// `bson::to_bson` reports itself as human readable and would store a
+/// string; build update documents with `bson::DateTime` values instead.
+pub mod timestamp {
+    use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, SecondsFormat, Utc};
+    use mongodb::bson::{self, Bson};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer, de::Error};
+
+    pub fn serialize<S: Serializer>(value: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&value.to_rfc3339_opts(SecondsFormat::Millis, true))
+        } else {
+            bson::DateTime::from_millis(value.timestamp_millis()).serialize(serializer)
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<DateTime<Utc>, D::Error> {
+        from_bson(Bson::deserialize(deserializer)?).map_err(D::Error::custom)
+    }
+
+    fn from_bson(value: Bson) -> Result<DateTime<Utc>, String> {
+        match value {
+            Bson::DateTime(value) => DateTime::from_timestamp_millis(value.timestamp_millis())
+                .ok_or_else(|| "datetime out of range".to_string()),
+            Bson::String(value) => parse(&value),
+            other => Err(format!("expected a datetime, found {}", other)),
+        }
+    }
+
+    /// Parses the ISO-8601 forms clients send: a full RFC 3339 timestamp,
+    /// a date and time without offset (taken as UTC, as `datetime-local`
+    /// inputs produce), or a bare `YYYY-MM-DD` date (midnight UTC).
+    pub fn parse(value: &str) -> Result<DateTime<Utc>, String> {
+        let value = value.trim();
+        if let Ok(value) = DateTime::parse_from_rfc3339(value) {
+            return Ok(value.with_timezone(&Utc));
+        }
+        for format in ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%dT%H:%M"] {
+            if let Ok(value) = NaiveDateTime::parse_from_str(value, format) {
+                return Ok(value.and_utc());
+            }
+        }
+        NaiveDate::parse_from_str(value, "%Y-%m-%d")
+            .map(|date| date.and_time(NaiveTime::MIN).and_utc())
+            .map_err(|_| format!("`{}` is not an ISO-8601 date", value))
+    }
+
+    pub fn to_bson(value: &DateTime<Utc>) -> bson::DateTime {
+        bson::DateTime::from_millis(value.timestamp_millis())
+    }
+
+    /// Same as the parent module, for `Option<DateTime<Utc>>` fields.
+    pub mod option {
+        use super::*;
+
+        pub fn serialize<S: Serializer>(
+            value: &Option<DateTime<Utc>>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            match value {
+                Some(value) => super::serialize(value, serializer),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Option<DateTime<Utc>>, D::Error> {
+            match Option::<Bson>::deserialize(deserializer)? {
+                None | Some(Bson::Null) => Ok(None),
+                Some(value) => from_bson(value).map(Some).map_err(D::Error::custom),
+            }
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -18,7 +107,100 @@ pub struct ChatRoom {
     pub id: Option<ObjectId>,
     pub user1: String,
     pub user2: String,
-    pub created_at: String,
+    #[serde(with = "timestamp")]
+    pub created_at: chrono::DateTime<Utc>,
+    /// Post the conversation is about. The same two users get a separate
+    /// room per post, plus one general room without a post.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post_id: Option<ObjectId>,
+}
+
+impl ChatRoom {
+    /// The participant who isn't `username`.
+    pub fn peer_of(&self, username: &str) -> &str {
+        if self.user1 == username { &self.user2 } else { &self.user1 }
+    }
+}
+
+fn room_ids(rooms: &[ChatRoom]) -> Vec<String> {
+    rooms.iter().filter_map(|room| room.id).map(|id| id.to_hex()).collect()
+}
+
+/// Characters of a message shown in the room list.
+const PREVIEW_CHARS: usize = 100;
+
+/// A room as listed on the Chats tab.
+#[derive(Serialize, Debug, Clone)]
+pub struct ChatRoomSummary {
+    #[serde(flatten)]
+    pub room: ChatRoom,
+    /// The other participant; missing if their account is gone.
+    pub peer: Option<UserPublic>,
+    /// Header for rooms about a post; missing once the post is deleted.
+    pub post: Option<PostSummary>,
+    pub last_message: Option<ChatPreview>,
+    pub unread_count: u64,
+    /// Time of the last message, or of the room's creation if it is empty.
+    #[serde(with = "timestamp")]
+    pub last_activity: chrono::DateTime<Utc>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ChatPreview {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub sender: String,
+    pub preview: String,
+    #[serde(with = "timestamp")]
+    pub send_at: chrono::DateTime<Utc>,
+    pub is_read: bool,
+}
+
+/// Enough of a post to head a conversation about it.
+#[derive(Serialize, Debug, Clone)]
+pub struct PostSummary {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub kind: PostKind,
+    pub category: Category,
+    pub description: String,
+    pub place: String,
+    pub image_link: String,
+    pub thumbnails: Option<Thumbnails>,
+    pub status: PostStatus,
+    pub user: String,
+}
+
+impl PostSummary {
+    fn from_post(post: MissingPost) -> Option<Self> {
+        Some(Self {
+            id: post.id?,
+            kind: post.kind,
+            category: post.category,
+            description: post.description,
+            place: post.place,
+            image_link: post.image_link,
+            thumbnails: post.image_id.as_ref().map(Thumbnails::for_image),
+            status: post.status,
+            user: post.user,
+        })
+    }
+}
+
+impl From<ChatMessage> for ChatPreview {
+    fn from(message: ChatMessage) -> Self {
+        let preview = match message.message.char_indices().nth(PREVIEW_CHARS) {
+            Some((end, _)) => format!("{}…", &message.message[..end]),
+            None => message.message,
+        };
+        Self {
+            id: message.id,
+            sender: message.sender,
+            preview,
+            send_at: message.send_at,
+            is_read: message.is_read,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -27,20 +209,32 @@ pub struct ChatMessage {
     pub id: Option<ObjectId>,
     pub char_id: String,
     pub sender: String,
-    pub send_at: String,
+    #[serde(with = "timestamp")]
+    pub send_at: chrono::DateTime<Utc>,
     pub message: String,
     pub is_read: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct User_Register {
+pub struct UserRegister {
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
     pub id: Option<ObjectId>,
     pub username: String,
     pub gmail: String,
-    pub PhoneNumber: String,
+    #[serde(rename = "PhoneNumber")]
+    pub phone_number: String,
     pub profile_picture: String,
     pub password: String,
+    #[serde(default)]
+    pub role: Role,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    #[default]
+    User,
+    Admin,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -50,377 +244,3343 @@ pub struct Comment {
     pub message: String,
 }
 
+/// Whether the poster lost the item or found someone else's.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PostKind {
+    #[default]
+    Lost,
+    Found,
+}
+
+/// What kind of item a post is about.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Category {
+    Electronics,
+    Keys,
+    IdCards,
+    Bags,
+    Clothing,
+    Books,
+    Bottles,
+    #[default]
+    Other,
+}
+
+impl Category {
+    pub const ALL: [Category; 8] = [
+        Category::Electronics,
+        Category::Keys,
+        Category::IdCards,
+        Category::Bags,
+        Category::Clothing,
+        Category::Books,
+        Category::Bottles,
+        Category::Other,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Category::Electronics => "electronics",
+            Category::Keys => "keys",
+            Category::IdCards => "id_cards",
+            Category::Bags => "bags",
+            Category::Clothing => "clothing",
+            Category::Books => "books",
+            Category::Bottles => "bottles",
+            Category::Other => "other",
+        }
+    }
+
+    /// Legacy posts have no `category` field and count as other.
+    fn filter_value(self) -> Bson {
+        match self {
+            Category::Other => doc! { "$in": ["other", null] }.into(),
+            category => category.as_str().into(),
+        }
+    }
+}
+
+const MAX_ATTRIBUTE_LEN: usize = 40;
+
+/// Optional structured details that help tell similar items apart.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ItemAttributes {
+    /// Stored lowercased so it can be filtered on.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub brand: Option<String>,
+    /// Last four characters of a serial or card number.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub serial_last4: Option<String>,
+}
+
+impl ItemAttributes {
+    /// Trims and normalizes the attributes, rejecting values that are too
+    /// long or a serial suffix that is not exactly four letters or digits.
+    pub fn validated(self) -> Result<Self> {
+        fn clean(value: Option<String>, field: &str) -> Result<Option<String>> {
+            let Some(value) = value.map(|value| value.trim().to_string()) else {
+                return Ok(None);
+            };
+            if value.is_empty() {
+                return Ok(None);
+            }
+            if value.chars().count() > MAX_ATTRIBUTE_LEN {
+                return Err(Error::InvalidInput(format!(
+                    "{} must be at most {} characters",
+                    field, MAX_ATTRIBUTE_LEN
+                )));
+            }
+            Ok(Some(value))
+        }
+
+        let serial_last4 = clean(self.serial_last4, "serial_last4")?;
+        if let Some(serial) = &serial_last4
+            && (serial.len() != 4 || !serial.chars().all(|c| c.is_ascii_alphanumeric()))
+        {
+            return Err(Error::InvalidInput(
+                "serial_last4 must be exactly 4 letters or digits".to_string(),
+            ));
+        }
+
+        Ok(Self {
+            color: clean(self.color, "color")?.map(|color| color.to_lowercase()),
+            brand: clean(self.brand, "brand")?,
+            serial_last4: serial_last4.map(|serial| serial.to_uppercase()),
+        })
+    }
+}
+
+/// Where a post is in its lifecycle. `Returned` and `Closed` are terminal.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PostStatus {
+    #[default]
+    Open,
+    Claimed,
+    Returned,
+    Closed,
+}
+
+impl PostStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PostStatus::Open => "open",
+            PostStatus::Claimed => "claimed",
+            PostStatus::Returned => "returned",
+            PostStatus::Closed => "closed",
+        }
+    }
+
+    pub fn can_transition_to(self, next: PostStatus) -> bool {
+        match self {
+            PostStatus::Open => next != PostStatus::Open,
+            // A claim that falls through puts the post back on the board.
+            PostStatus::Claimed => next != PostStatus::Claimed,
+            PostStatus::Returned | PostStatus::Closed => false,
+        }
+    }
+
+    /// Filter matching posts in this status. Legacy posts have no `status`
+    /// field and count as open.
+    fn filter_value(self) -> Bson {
+        match self {
+            PostStatus::Open => doc! { "$in": ["open", null] }.into(),
+            status => status.as_str().into(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StatusChange {
+    pub from: PostStatus,
+    pub to: PostStatus,
+    pub by: String,
+    #[serde(with = "timestamp")]
+    pub at: chrono::DateTime<Utc>,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct MissingPost {
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
     pub id: Option<ObjectId>,
+    /// Posts created before kinds existed were all reports of lost items.
+    #[serde(default)]
+    pub kind: PostKind,
+    #[serde(default)]
+    pub category: Category,
+    #[serde(default)]
+    pub attributes: ItemAttributes,
     pub description: String,
-    pub date: String,
+    /// When the item was lost or found.
+    #[serde(with = "timestamp")]
+    pub date: chrono::DateTime<Utc>,
     pub place: String,
     pub image_link: String,
     pub user: String,
     pub comments: Vec<Comment>,
+    #[serde(default)]
+    pub status: PostStatus,
+    #[serde(default)]
+    pub status_history: Vec<StatusChange>,
+    /// Set when the author removes the post. Deleted posts are hidden
+    /// everywhere except the moderation listing.
+    #[serde(default, with = "timestamp::option")]
+    pub deleted_at: Option<chrono::DateTime<Utc>>,
+    #[serde(default)]
+    pub deleted_by: Option<String>,
+    /// Campus location from the directory; `place` then holds free-text
+    /// detail such as "2nd floor, near the printers".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location_id: Option<ObjectId>,
+    /// Where the item was lost or found, for "near me" searches.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub geo: Option<GeoPoint>,
+    /// `geo` was copied from the directory location rather than given by
+    /// the author, so it follows the location when that moves.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub geo_derived: bool,
+    /// Upload behind `image_link` when the photo was uploaded to us rather
+    /// than linked from elsewhere; its thumbnails are served alongside.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_id: Option<ObjectId>,
+    /// Perceptual hash of the uploaded photo, copied from the upload.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_hash: Option<String>,
+    /// Earlier posts by the same author whose photo looks the same,
+    /// flagged when the post was created.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub duplicate_of: Vec<ObjectId>,
+    /// Questions a finder asks claimants, e.g. "what sticker is on the
+    /// back?". Never included in feed responses.
+    #[serde(default)]
+    pub verification_questions: Vec<String>,
 }
 
-#[derive(Debug, Deserialize)]
-pub struct Post_payload {
-    pub id: String,
-    pub user_id: String,
-    pub title: String,
-    pub content: String,
-    pub comments: Vec<Comment>,
+/// Checks the date an item was lost or found is plausible: not in the
+/// future, give or take a day for time zones.
+pub fn validate_post_date(date: chrono::DateTime<Utc>) -> Result<chrono::DateTime<Utc>> {
+    if date > Utc::now() + chrono::Duration::days(1) {
+        return Err(Error::InvalidInput("The date cannot be in the future".to_string()));
+    }
+    Ok(date)
 }
 
-#[derive(Clone)]
-pub struct ModelController {
-    pub user_collection: Collection<User_Register>,
-    pub post_collection: Collection<MissingPost>,
-    pub chat_room_collection: Collection<ChatRoom>,
-    pub chat_message_collection: Collection<ChatMessage>,
+const MAX_VERIFICATION_QUESTIONS: usize = 5;
+const MAX_QUESTION_LEN: usize = 200;
+const MAX_ANSWER_LEN: usize = 500;
+
+/// Trims the finder's questions and checks they fit on a post of `kind`.
+pub fn validate_questions(kind: PostKind, questions: Vec<String>) -> Result<Vec<String>> {
+    let questions: Vec<String> = questions
+        .into_iter()
+        .map(|question| question.trim().to_string())
+        .filter(|question| !question.is_empty())
+        .collect();
+    if questions.is_empty() {
+        return Ok(questions);
+    }
+    if kind != PostKind::Found {
+        return Err(Error::InvalidInput(
+            "Only found posts can have verification questions".to_string(),
+        ));
+    }
+    if questions.len() > MAX_VERIFICATION_QUESTIONS {
+        return Err(Error::InvalidInput(format!(
+            "At most {} verification questions are allowed",
+            MAX_VERIFICATION_QUESTIONS
+        )));
+    }
+    if questions.iter().any(|q| q.chars().count() > MAX_QUESTION_LEN) {
+        return Err(Error::InvalidInput(format!(
+            "Verification questions must be at most {} characters",
+            MAX_QUESTION_LEN
+        )));
+    }
+    Ok(questions)
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct UserPublic {
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClaimStatus {
+    #[default]
+    Pending,
+    Accepted,
+    Rejected,
+}
+
+impl ClaimStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ClaimStatus::Pending => "pending",
+            ClaimStatus::Accepted => "accepted",
+            ClaimStatus::Rejected => "rejected",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ClaimAnswer {
+    pub question: String,
+    pub answer: String,
+}
+
+/// Someone asking a finder to hand over an item, with their answers to the
+/// post's verification questions.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Claim {
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
     pub id: Option<ObjectId>,
-    pub username: String,
-    pub gmail: String,
-    pub PhoneNumber: String,
-    pub profile_picture: String,
+    pub post_id: ObjectId,
+    pub claimant: String,
+    /// Author of the found post, who reviews the claim.
+    pub finder: String,
+    pub answers: Vec<ClaimAnswer>,
+    #[serde(default)]
+    pub message: Option<String>,
+    pub status: ClaimStatus,
+    #[serde(with = "timestamp")]
+    pub created_at: chrono::DateTime<Utc>,
+    #[serde(default, with = "timestamp::option")]
+    pub reviewed_at: Option<chrono::DateTime<Utc>>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct PostWithUser {
-    pub id: Option<ObjectId>,
-    pub description: String,
-    pub date: String,
-    pub place: String,
-    pub image_link: String,
-    pub user: UserPublic,
-    pub comments: Vec<Comment>,
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HandoverStatus {
+    #[default]
+    Pending,
+    Completed,
+    /// The post left claimed before the handover happened.
+    Voided,
 }
 
-impl ModelController {
-    pub fn new(db: mongodb::Database) -> Self {
-        Self {
-            user_collection: db.collection("users"),
-            post_collection: db.collection("posts"),
-            chat_room_collection: db.collection("chat_rooms"),
-            chat_message_collection: db.collection("chat"),
+impl HandoverStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            HandoverStatus::Pending => "pending",
+            HandoverStatus::Completed => "completed",
+            HandoverStatus::Voided => "voided",
         }
     }
+}
 
-    pub async fn register_user(&self, user_data: User_Register) -> Result<()> {
-        let filter = doc! { "gmail": &user_data.gmail };
-        if self.user_collection.find_one(filter, None).await?.is_some() {
-            return Err(Error::UserWithMailExists);
-        }
+/// Record of an item changing hands after a claim was accepted. The owner
+/// shows `code` to the finder, who submits it to mark the post returned.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Handover {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub claim_id: ObjectId,
+    pub post_id: ObjectId,
+    /// The claimant the item is handed to.
+    pub owner: String,
+    pub finder: String,
+    /// Only ever shown to the owner, and removed once used.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+    pub status: HandoverStatus,
+    /// Category of the post, copied at completion for the return stats.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub category: Option<Category>,
+    #[serde(with = "timestamp")]
+    pub created_at: chrono::DateTime<Utc>,
+    #[serde(default, with = "timestamp::option")]
+    pub completed_at: Option<chrono::DateTime<Utc>>,
+}
 
-        self.user_collection.insert_one(user_data, None).await?;
-        Ok(())
+impl Handover {
+    /// Contents of the QR code the owner can show instead of reading the
+    /// code aloud; the finder's app scans it and submits both values.
+    pub fn qr_payload(&self) -> Option<String> {
+        let code = self.code.as_ref()?;
+        let claim_id = self.claim_id.to_hex();
+        Some(format!("itshere://handover?claim={claim_id}&code={code}"))
     }
-    pub async fn find_user_by_username(&self, username: &str) -> Result<Option<User_Register>> {
-        let user = self
-            .user_collection
-            .find_one(doc! { "username": username }, None)
-            .await?;
+}
 
-        Ok(user)
-    }
+#[derive(Debug, Serialize)]
+pub struct CategoryCount {
+    pub category: Category,
+    pub count: i64,
+}
 
-    pub async fn add_post(&self, post: MissingPost) -> Result<()> {
-        self.post_collection.insert_one(post, None).await?;
-        Ok(())
-    }
+#[derive(Debug, Serialize)]
+pub struct ReturnStats {
+    pub total_returns: i64,
+    pub returns_last_30_days: i64,
+    /// Average time from posting a found item to handing it over.
+    pub avg_hours_to_return: Option<f64>,
+    pub by_category: Vec<CategoryCount>,
+}
 
-    pub async fn getallpost(&self) -> Result<Vec<PostWithUser>> {
-        let mut cursor = self
-            .post_collection
-            .find(None, FindOptions::default())
-            .await?;
+/// An uploaded image. The bytes live in [`Storage`] under `key`; clients
+/// only ever see the id and the URL built from it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Upload {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub owner: String,
+    /// Key of the full-size image.
+    pub key: String,
+    pub content_type: String,
+    pub size: i64,
+    /// Missing on uploads stored before thumbnails were generated; those
+    /// serve the full image for every size.
+    #[serde(default)]
+    pub thumbnails: Option<ThumbnailKeys>,
+    #[serde(default)]
+    pub width: Option<u32>,
+    #[serde(default)]
+    pub height: Option<u32>,
+    /// Perceptual hash of the image, see [`imaging::dhash`].
+    #[serde(default)]
+    pub image_hash: Option<String>,
+    #[serde(with = "timestamp")]
+    pub created_at: chrono::DateTime<Utc>,
+}
 
-        let mut posts = Vec::new();
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ThumbnailKeys {
+    pub detail: String,
+    pub feed: String,
+}
 
-        while let Some(post) = cursor.try_next().await? {
-            let user = self
-                .user_collection
-                .find_one(doc! { "username": &post.user }, None)
-                .await?
-                .ok_or(Error::DatabaseError("User does not exist".to_string()))?;
-
-            let public_user = UserPublic {
-                id: user.id,
-                username: user.username,
-                gmail: user.gmail,
-                PhoneNumber: user.PhoneNumber,
-                profile_picture: user.profile_picture,
-            };
+impl Upload {
+    /// Stable URL the backend serves the image from.
+    pub fn url(&self) -> String {
+        image_url(&self.id.to_hex())
+    }
 
-            posts.push(PostWithUser {
-                id: post.id,
-                description: post.description,
-                date: post.date,
-                place: post.place,
-                image_link: post.image_link,
-                user: public_user,
-                comments: post.comments,
-            });
+    fn key_for(&self, size: ImageSize) -> &str {
+        match (size, &self.thumbnails) {
+            (ImageSize::Detail, Some(thumbnails)) => &thumbnails.detail,
+            (ImageSize::Feed, Some(thumbnails)) => &thumbnails.feed,
+            _ => &self.key,
         }
+    }
+}
 
-        Ok(posts)
+pub fn image_url(image_id: &str) -> String {
+    format!("{}/api/images/{}", config().server.public_url, image_id)
+}
+
+/// Thumbnail URLs for a post photo that was uploaded to us.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Thumbnails {
+    pub feed: String,
+    pub detail: String,
+}
+
+impl Thumbnails {
+    pub fn for_image(image_id: &ObjectId) -> Self {
+        let url = image_url(&image_id.to_hex());
+        Self {
+            feed: format!("{}/{}", url, ImageSize::Feed.as_str()),
+            detail: format!("{}/{}", url, ImageSize::Detail.as_str()),
+        }
     }
+}
 
-    pub async fn edit_username(&self, user: &User_Register, gmail: &str) -> Result<()> {
-        let user_id = match &user.id {
-            Some(id) => id,
-            None => return Err(Error::UserNotFound),
-        };
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LocationKind {
+    #[default]
+    Building,
+    Room,
+}
 
-        let existing_user = self
-            .user_collection
-            .find_one(doc! { "gmail": gmail }, None)
-            .await?;
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Coordinates {
+    pub lat: f64,
+    pub lng: f64,
+}
 
-        if existing_user.is_some() {
-            return Err(Error::UserWithMailExists);
+impl Coordinates {
+    pub fn validated(self) -> Result<Self> {
+        if !(-90.0..=90.0).contains(&self.lat) || !(-180.0..=180.0).contains(&self.lng) {
+            return Err(Error::InvalidInput("Coordinates are out of range".to_string()));
         }
-        let filter = doc! { "_id": user_id };
-        let update = doc! {
-            "$set": { "gmail": &gmail }
-        };
+        Ok(self)
+    }
+}
 
-        let result = self
-            .user_collection
-            .update_one(filter, update, None)
-            .await?;
+/// GeoJSON point as Mongo's `2dsphere` index expects it. Note the order:
+/// longitude first.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GeoPoint {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub coordinates: [f64; 2],
+}
 
-        if result.matched_count == 0 {
-            return Err(Error::UserNotFound);
+impl From<Coordinates> for GeoPoint {
+    fn from(coordinates: Coordinates) -> Self {
+        Self {
+            kind: "Point".to_string(),
+            coordinates: [coordinates.lng, coordinates.lat],
         }
+    }
+}
 
-        Ok(())
+impl From<&GeoPoint> for Coordinates {
+    fn from(point: &GeoPoint) -> Self {
+        Self {
+            lat: point.coordinates[1],
+            lng: point.coordinates[0],
+        }
     }
+}
 
-    pub async fn changephonenumber(&self, user: &User_Register, phoneNumber: &str) -> Result<()> {
-        let user_id = match &user.id {
-            Some(id) => id,
-            None => return Err(Error::UserNotFound),
-        };
+/// An entry of the campus directory: a building, or a room inside one.
+/// Posts point at these so "Library" and "Central Lib" end up the same.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Location {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub name: String,
+    #[serde(default)]
+    pub kind: LocationKind,
+    /// Building a room belongs to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_id: Option<ObjectId>,
+    /// Name of the parent building, kept in sync so autocomplete can show
+    /// "Room 204, Library" without a second query.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub building: Option<String>,
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub coordinates: Option<Coordinates>,
+    /// Lowercased words of the name and aliases, for prefix autocomplete.
+    #[serde(default)]
+    pub search_terms: Vec<String>,
+}
 
-        let filter = doc! { "_id": user_id };
-        let update = doc! {
-            "$set": { "PhoneNumber": &phoneNumber }
-        };
+/// Body of the admin endpoints that create or replace a location.
+#[derive(Clone, Debug, Deserialize)]
+pub struct LocationInput {
+    pub name: String,
+    #[serde(default)]
+    pub kind: LocationKind,
+    pub parent_id: Option<String>,
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    pub coordinates: Option<Coordinates>,
+}
 
-        let result = self
-            .user_collection
-            .update_one(filter, update, None)
-            .await?;
+const MAX_LOCATION_NAME_LEN: usize = 100;
+const MAX_LOCATION_ALIASES: usize = 10;
+pub const DEFAULT_LOCATION_LIMIT: i64 = 10;
+pub const MAX_LOCATION_LIMIT: i64 = 50;
 
-        if result.matched_count == 0 {
-            return Err(Error::UserNotFound);
-        }
+fn location_terms(name: &str, aliases: &[String], building: Option<&str>) -> Vec<String> {
+    let mut terms: Vec<String> = std::iter::once(name)
+        .chain(aliases.iter().map(String::as_str))
+        .chain(building)
+        .flat_map(|text| text.split(|c: char| !c.is_alphanumeric()))
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect();
+    terms.sort();
+    terms.dedup();
+    terms
+}
 
-        Ok(())
-    }
-    pub async fn get_posts_by_user(&self, username: &str) -> Result<Vec<PostWithUser>> {
-        let filter = doc! { "user": username };
+/// Fields the author may change after posting; missing fields are kept.
+#[derive(Debug, Default, Deserialize)]
+pub struct PostUpdate {
+    pub kind: Option<PostKind>,
+    pub category: Option<Category>,
+    pub attributes: Option<ItemAttributes>,
+    pub description: Option<String>,
+    #[serde(default, with = "timestamp::option")]
+    pub date: Option<chrono::DateTime<Utc>>,
+    pub place: Option<String>,
+    pub image_link: Option<String>,
+    pub location_id: Option<String>,
+    pub coordinates: Option<Coordinates>,
+    /// Id of an upload to use as the post's photo; replaces `image_link`.
+    pub image_id: Option<String>,
+    pub verification_questions: Option<Vec<String>>,
+}
 
-        let mut cursor = self
-            .post_collection
-            .find(filter, FindOptions::default())
-            .await?;
+/// Per-signal scores of a lost/found pair, each between 0 and 1. See the
+/// `matching` module for how they are computed and weighted.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ScoreBreakdown {
+    pub category: f64,
+    pub text: f64,
+    pub attributes: f64,
+    pub place: f64,
+    pub date: f64,
+    /// Photo similarity; only present when both posts have an uploaded photo.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image: Option<f64>,
+}
 
-        let mut posts = Vec::new();
+/// A stored candidate pairing of a lost post with a found post.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PostMatch {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub lost_post: ObjectId,
+    pub found_post: ObjectId,
+    pub score: f64,
+    pub breakdown: ScoreBreakdown,
+    #[serde(with = "timestamp")]
+    pub updated_at: chrono::DateTime<Utc>,
+}
 
-        while let Some(post) = cursor.try_next().await? {
-            let user = self
-                .user_collection
-                .find_one(doc! { "username": &post.user }, None)
-                .await?
-                .ok_or(Error::DatabaseError("User does not exist".to_string()))?;
-
-            let public_user = UserPublic {
-                id: user.id,
-                username: user.username,
-                gmail: user.gmail,
-                PhoneNumber: user.PhoneNumber,
-                profile_picture: user.profile_picture,
-            };
+/// A match as shown to one side: the other side's post and the score.
+#[derive(Debug, Serialize)]
+pub struct MatchResult {
+    pub post: PostWithUser,
+    pub score: f64,
+    pub breakdown: ScoreBreakdown,
+}
 
-            posts.push(PostWithUser {
-                id: post.id,
-                description: post.description,
-                date: post.date,
-                place: post.place,
-                image_link: post.image_link,
-                user: public_user,
-                comments: post.comments,
-            });
-        }
+const MAX_MATCH_CANDIDATES: i64 = 500;
+const MAX_MATCHES_SHOWN: i64 = 20;
 
-        Ok(posts)
+/// The `matches` fields holding a post of `kind` and its counterpart.
+fn match_fields(kind: PostKind) -> (&'static str, &'static str) {
+    match kind {
+        PostKind::Lost => ("lost_post", "found_post"),
+        PostKind::Found => ("found_post", "lost_post"),
     }
+}
 
-    pub async fn add_comment_to_post(&self, post_id: &str, comment: Comment) -> Result<()> {
-        let obj_id = ObjectId::parse_str(post_id).map_err(|_| Error::UserNotFound)?;
-
-        let filter = doc! { "_id": obj_id };
-        let update = doc! {
-            "$push": { "comments": to_bson(&comment).map_err(|_| Error::UserNotFound )? }
+/// A login session. The client holds the refresh token; only its hash is
+/// stored. Access tokens carry the session id so revoking the session
+/// invalidates them too.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Session {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub username: String,
+    pub refresh_token_hash: String,
+    /// Hash of the token that was rotated out, kept to detect reuse.
+    pub previous_token_hash: Option<String>,
+    pub created_at: DateTime,
+    pub last_used_at: DateTime,
+    pub expires_at: DateTime,
+    pub revoked_at: Option<DateTime>,
+}
+
+/// Returned when a session is created or its refresh token is rotated.
+#[derive(Debug)]
+pub struct IssuedSession {
+    pub session_id: ObjectId,
+    pub username: String,
+    pub refresh_token: String,
+}
+
+#[derive(Clone)]
+pub struct ModelController {
+    pub user_collection: Collection<UserRegister>,
+    pub post_collection: Collection<MissingPost>,
+    pub chat_room_collection: Collection<ChatRoom>,
+    pub chat_message_collection: Collection<ChatMessage>,
+    pub session_collection: Collection<Session>,
+    pub match_collection: Collection<PostMatch>,
+    pub claim_collection: Collection<Claim>,
+    pub handover_collection: Collection<Handover>,
+    pub upload_collection: Collection<Upload>,
+    pub location_collection: Collection<Location>,
+    pub migration_collection: Collection<AppliedMigration>,
+    pub storage: Arc<dyn Storage>,
+    pub hub: Arc<dyn ChatHub>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UserPublic {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub username: String,
+    pub gmail: String,
+    #[serde(rename = "PhoneNumber")]
+    pub phone_number: String,
+    pub profile_picture: String,
+}
+
+impl From<UserRegister> for UserPublic {
+    fn from(user: UserRegister) -> Self {
+        Self {
+            id: user.id,
+            username: user.username,
+            gmail: user.gmail,
+            phone_number: user.phone_number,
+            profile_picture: user.profile_picture,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PostWithUser {
+    pub id: Option<ObjectId>,
+    pub kind: PostKind,
+    pub category: Category,
+    pub attributes: ItemAttributes,
+    pub description: String,
+    #[serde(with = "timestamp")]
+    pub date: chrono::DateTime<Utc>,
+    pub place: String,
+    pub location_id: Option<ObjectId>,
+    pub coordinates: Option<Coordinates>,
+    pub image_link: String,
+    /// Present when the photo was uploaded to us.
+    pub thumbnails: Option<Thumbnails>,
+    pub user: UserPublic,
+    pub comments: Vec<Comment>,
+    pub status: PostStatus,
+    pub status_history: Vec<StatusChange>,
+}
+
+impl PostWithUser {
+    pub fn new(post: MissingPost, user: UserPublic) -> Self {
+        Self {
+            id: post.id,
+            kind: post.kind,
+            category: post.category,
+            attributes: post.attributes,
+            description: post.description,
+            date: post.date,
+            place: post.place,
+            location_id: post.location_id,
+            coordinates: post.geo.as_ref().map(Coordinates::from),
+            image_link: post.image_link,
+            thumbnails: post.image_id.as_ref().map(Thumbnails::for_image),
+            user,
+            comments: post.comments,
+            status: post.status,
+            status_history: post.status_history,
+        }
+    }
+}
+
+const DEFAULT_MESSAGE_PAGE: i64 = 50;
+const MAX_MESSAGE_PAGE: i64 = 200;
+
+/// Chat history parameters. `since` syncs forward from a timestamp,
+/// `before` pages back from one, and with neither the latest messages are
+/// returned. Both bounds are exclusive, so polling with the `send_at` of
+/// the last message held doesn't return it again. `cursor` continues where the previous page's `next_cursor`
+/// left off, in the same direction. At most one of the three may be given.
+#[derive(Debug, Default, Deserialize)]
+pub struct MessageQuery {
+    #[serde(default, with = "timestamp::option")]
+    pub since: Option<chrono::DateTime<Utc>>,
+    #[serde(default, with = "timestamp::option")]
+    pub before: Option<chrono::DateTime<Utc>>,
+    pub limit: Option<i64>,
+    pub cursor: Option<String>,
+}
+
+impl MessageQuery {
+    fn limit(&self) -> i64 {
+        self.limit.unwrap_or(DEFAULT_MESSAGE_PAGE).clamp(1, MAX_MESSAGE_PAGE)
+    }
+
+    /// Where the page starts, if anywhere, and whether it runs forward.
+    fn position(&self) -> Result<(Option<MessagePosition>, bool)> {
+        Ok(match (&self.cursor, self.since, self.before) {
+            (Some(cursor), None, None) => {
+                let (position, forward) = decode_message_cursor(cursor)?;
+                (Some(position), forward)
+            }
+            (None, Some(since), None) => (Some(MessagePosition::Since(since)), true),
+            (None, None, Some(before)) => (Some(MessagePosition::Before(before)), false),
+            (None, None, None) => (None, false),
+            _ => {
+                return Err(Error::InvalidInput(
+                    "Use only one of cursor, since and before".to_string(),
+                ));
+            }
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct MessagePage {
+    pub messages: Vec<ChatMessage>,
+    pub next_cursor: Option<String>,
+}
+
+enum MessagePosition {
+    Since(chrono::DateTime<Utc>),
+    Before(chrono::DateTime<Utc>),
+    /// Just past this message, in the page's direction.
+    After(DateTime, ObjectId),
+}
+
+impl MessagePosition {
+    fn filter(&self, forward: bool) -> Document {
+        match self {
+            MessagePosition::Since(since) => doc! { "send_at": { "$gt": timestamp::to_bson(since) } },
+            MessagePosition::Before(before) => doc! { "send_at": { "$lt": timestamp::to_bson(before) } },
+            MessagePosition::After(at, id) => {
+                let op = if forward { "$gt" } else { "$lt" };
+                doc! {
+                    "$or": [
+                        { "send_at": { op: at } },
+                        { "send_at": at, "_id": { op: id } },
+                    ]
+                }
+            }
+        }
+    }
+}
+
+fn encode_message_cursor(message: &ChatMessage, forward: bool) -> Option<String> {
+    let cursor = doc! {
+        "at": timestamp::to_bson(&message.send_at),
+        "id": message.id?,
+        "forward": forward,
+    };
+    let mut bytes = Vec::new();
+    cursor.to_writer(&mut bytes).ok()?;
+    Some(URL_SAFE_NO_PAD.encode(bytes))
+}
+
+fn decode_message_cursor(cursor: &str) -> Result<(MessagePosition, bool)> {
+    let invalid = || Error::InvalidInput("Invalid cursor".to_string());
+    let bytes = URL_SAFE_NO_PAD.decode(cursor).map_err(|_| invalid())?;
+    let cursor = Document::from_reader(bytes.as_slice()).map_err(|_| invalid())?;
+    let at = *cursor.get_datetime("at").map_err(|_| invalid())?;
+    let id = cursor.get_object_id("id").map_err(|_| invalid())?;
+    let forward = cursor.get_bool("forward").map_err(|_| invalid())?;
+    Ok((MessagePosition::After(at, id), forward))
+}
+
+/// A post returned by the "near me" search, with its distance in meters.
+#[derive(Debug, Serialize)]
+pub struct NearbyPost {
+    #[serde(flatten)]
+    pub post: PostWithUser,
+    pub distance_m: f64,
+}
+
+pub const DEFAULT_NEAR_RADIUS_M: f64 = 300.0;
+pub const MAX_NEAR_RADIUS_M: f64 = 5_000.0;
+
+/// Feed and search filters, taken from the query string. Returned and
+/// closed posts are hidden unless a status is asked for or
+/// `include_resolved` is set. `from` and `to` bound the post's `date`,
+/// both inclusive.
+#[derive(Debug, Default, Deserialize)]
+pub struct PostFilter {
+    pub kind: Option<PostKind>,
+    pub category: Option<Category>,
+    pub color: Option<String>,
+    pub status: Option<PostStatus>,
+    pub location_id: Option<String>,
+    #[serde(default)]
+    pub include_resolved: bool,
+    pub from: Option<chrono::NaiveDate>,
+    pub to: Option<chrono::NaiveDate>,
+}
+
+impl PostFilter {
+    fn to_document(&self) -> Result<Document> {
+        let mut filter = doc! { "deleted_at": null };
+        match self.kind {
+            Some(PostKind::Found) => {
+                filter.insert("kind", "found");
+            }
+            // Legacy posts have no `kind` field and count as lost.
+            Some(PostKind::Lost) => {
+                filter.insert("kind", doc! { "$ne": "found" });
+            }
+            None => {}
+        }
+        if let Some(category) = self.category {
+            filter.insert("category", category.filter_value());
+        }
+        if let Some(color) = &self.color {
+            filter.insert("attributes.color", color.trim().to_lowercase());
+        }
+        if let Some(location_id) = &self.location_id {
+            let location_id = ObjectId::parse_str(location_id).map_err(|_| Error::InvalidId)?;
+            filter.insert("location_id", location_id);
+        }
+        match self.status {
+            Some(status) => {
+                filter.insert("status", status.filter_value());
+            }
+            None if !self.include_resolved => {
+                filter.insert("status", doc! { "$nin": ["returned", "closed"] });
+            }
+            None => {}
+        }
+        let start_of = |day: chrono::NaiveDate| {
+            timestamp::to_bson(&day.and_time(chrono::NaiveTime::MIN).and_utc())
+        };
+        let mut date = Document::new();
+        if let Some(from) = self.from {
+            date.insert("$gte", start_of(from));
+        }
+        if let Some(to) = self.to.and_then(|to| to.succ_opt()) {
+            date.insert("$lt", start_of(to));
+        }
+        if !date.is_empty() {
+            filter.insert("date", date);
+        }
+        Ok(filter)
+    }
+}
+
+/// Whether `error` is a unique index rejecting a write (code 11000).
+pub(crate) fn is_duplicate_key(error: &mongodb::error::Error) -> bool {
+    matches!(
+        &*error.kind,
+        ErrorKind::Write(WriteFailure::WriteError(e)) if e.code == 11000
+    )
+}
+
+/// Separates the `author` joined by [`ModelController::author_lookup`] from
+/// the post document. `None` when the author no longer exists.
+fn split_author(mut document: Document) -> Result<(MissingPost, Option<UserPublic>)> {
+    let author = match document.remove("author") {
+        Some(Bson::Document(author)) => {
+            Some(from_document(author).map_err(|e| Error::DatabaseError(e.to_string()))?)
+        }
+        _ => None,
+    };
+    let post: MissingPost =
+        from_document(document).map_err(|e| Error::DatabaseError(e.to_string()))?;
+    Ok((post, author))
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PostSort {
+    #[default]
+    Newest,
+    Oldest,
+    /// Most recent `date` (when the item was lost or found) first.
+    DateLost,
+}
+
+const DEFAULT_PAGE_SIZE: i64 = 20;
+pub const MAX_PAGE_SIZE: i64 = 100;
+
+/// Paging parameters for the feed. `cursor` is the `next_cursor` returned
+/// by the previous page.
+#[derive(Debug, Default, Deserialize)]
+pub struct PageRequest {
+    #[serde(default)]
+    pub sort: PostSort,
+    pub limit: Option<i64>,
+    pub cursor: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PostPage {
+    pub posts: Vec<PostWithUser>,
+    pub next_cursor: Option<String>,
+}
+
+impl PageRequest {
+    fn limit(&self) -> i64 {
+        self.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE)
+    }
+
+    fn sort_document(&self) -> Document {
+        match self.sort {
+            PostSort::Newest => doc! { "_id": -1 },
+            PostSort::Oldest => doc! { "_id": 1 },
+            PostSort::DateLost => doc! { "date": -1, "_id": -1 },
+        }
+    }
+
+    /// Cursors are the sort key of the last post on the page, stored as
+    /// BSON so the value keeps its type, then base64url encoded.
+    fn encode_cursor(&self, post: &MissingPost) -> Option<String> {
+        let mut cursor = doc! { "id": post.id? };
+        if self.sort == PostSort::DateLost {
+            cursor.insert("date", timestamp::to_bson(&post.date));
+        }
+        let mut bytes = Vec::new();
+        cursor.to_writer(&mut bytes).ok()?;
+        Some(URL_SAFE_NO_PAD.encode(bytes))
+    }
+
+    /// Condition selecting the posts after the cursor in the current order.
+    fn cursor_filter(&self) -> Result<Option<Document>> {
+        let Some(cursor) = &self.cursor else {
+            return Ok(None);
+        };
+        let invalid = || Error::InvalidInput("Invalid cursor".to_string());
+        let bytes = URL_SAFE_NO_PAD.decode(cursor).map_err(|_| invalid())?;
+        let cursor = Document::from_reader(bytes.as_slice()).map_err(|_| invalid())?;
+        let id = cursor.get_object_id("id").map_err(|_| invalid())?;
+
+        Ok(Some(match self.sort {
+            PostSort::Newest => doc! { "_id": { "$lt": id } },
+            PostSort::Oldest => doc! { "_id": { "$gt": id } },
+            PostSort::DateLost => {
+                let date = cursor.get("date").cloned().ok_or_else(invalid)?;
+                doc! {
+                    "$or": [
+                        { "date": { "$lt": &date } },
+                        { "date": &date, "_id": { "$lt": id } },
+                    ]
+                }
+            }
+        }))
+    }
+}
+
+impl ModelController {
+    pub fn new(db: mongodb::Database, storage: Arc<dyn Storage>, hub: Arc<dyn ChatHub>) -> Self {
+        Self {
+            user_collection: db.collection("users"),
+            post_collection: db.collection("posts"),
+            chat_room_collection: db.collection("chat_rooms"),
+            chat_message_collection: db.collection("chat"),
+            session_collection: db.collection("sessions"),
+            match_collection: db.collection("matches"),
+            claim_collection: db.collection("claims"),
+            handover_collection: db.collection("handovers"),
+            upload_collection: db.collection("uploads"),
+            location_collection: db.collection("locations"),
+            migration_collection: db.collection("migrations"),
+            storage,
+            hub,
+        }
+    }
+
+    pub async fn init_indexes(&self) -> Result<()> {
+        self.chat_message_collection
+            .create_indexes(
+                [
+                    IndexModel::builder().keys(doc! { "char_id": 1, "send_at": 1 }).build(),
+                    IndexModel::builder().keys(doc! { "char_id": 1, "is_read": 1 }).build(),
+                ],
+                None,
+            )
+            .await?;
+        self.chat_room_collection
+            .create_indexes(
+                [
+                    IndexModel::builder().keys(doc! { "user1": 1, "user2": 1, "post_id": 1 }).build(),
+                    IndexModel::builder().keys(doc! { "post_id": 1 }).build(),
+                ],
+                None,
+            )
+            .await?;
+        self.post_collection
+            .create_index(IndexModel::builder().keys(doc! { "geo": "2dsphere" }).build(), None)
+            .await?;
+        self.location_collection
+            .create_indexes(
+                [
+                    IndexModel::builder().keys(doc! { "search_terms": 1 }).build(),
+                    IndexModel::builder().keys(doc! { "parent_id": 1 }).build(),
+                ],
+                None,
+            )
+            .await?;
+        self.upload_collection
+            .create_index(IndexModel::builder().keys(doc! { "owner": 1 }).build(), None)
+            .await?;
+        self.handover_collection
+            .create_indexes(
+                [
+                    IndexModel::builder()
+                        .keys(doc! { "claim_id": 1 })
+                        .options(IndexOptions::builder().unique(true).build())
+                        .build(),
+                    IndexModel::builder().keys(doc! { "owner": 1, "completed_at": -1 }).build(),
+                    IndexModel::builder().keys(doc! { "finder": 1, "completed_at": -1 }).build(),
+                    IndexModel::builder().keys(doc! { "status": 1, "completed_at": -1 }).build(),
+                ],
+                None,
+            )
+            .await?;
+        self.claim_collection
+            .create_indexes(
+                [
+                    IndexModel::builder().keys(doc! { "post_id": 1, "status": 1 }).build(),
+                    IndexModel::builder().keys(doc! { "claimant": 1, "_id": -1 }).build(),
+                    // One pending claim per claimant and post, even when two
+                    // submissions race past the check in create_claim.
+                    IndexModel::builder()
+                        .keys(doc! { "post_id": 1, "claimant": 1 })
+                        .options(
+                            IndexOptions::builder()
+                                .unique(true)
+                                .partial_filter_expression(
+                                    doc! { "status": ClaimStatus::Pending.as_str() },
+                                )
+                                .build(),
+                        )
+                        .build(),
+                ],
+                None,
+            )
+            .await?;
+        self.match_collection
+            .create_indexes(
+                [
+                    IndexModel::builder()
+                        .keys(doc! { "lost_post": 1, "found_post": 1 })
+                        .options(IndexOptions::builder().unique(true).build())
+                        .build(),
+                    IndexModel::builder().keys(doc! { "lost_post": 1, "score": -1 }).build(),
+                    IndexModel::builder().keys(doc! { "found_post": 1, "score": -1 }).build(),
+                ],
+                None,
+            )
+            .await?;
+        // Feed queries join authors on `username`.
+        self.user_collection
+            .create_index(IndexModel::builder().keys(doc! { "username": 1 }).build(), None)
+            .await?;
+        self.post_collection
+            .create_indexes(
+                [
+                    IndexModel::builder()
+                        .keys(doc! { "description": "text", "place": "text" })
+                        .options(
+                            IndexOptions::builder()
+                                .name("post_text".to_string())
+                                .weights(doc! { "description": 2, "place": 1 })
+                                .build(),
+                        )
+                        .build(),
+                    IndexModel::builder().keys(doc! { "user": 1, "_id": -1 }).build(),
+                    IndexModel::builder().keys(doc! { "date": -1, "_id": -1 }).build(),
+                ],
+                None,
+            )
+            .await?;
+        self.session_collection
+            .create_indexes(
+                [
+                    IndexModel::builder()
+                        .keys(doc! { "refresh_token_hash": 1 })
+                        .options(IndexOptions::builder().unique(true).build())
+                        .build(),
+                    IndexModel::builder()
+                        .keys(doc! { "previous_token_hash": 1 })
+                        .build(),
+                    IndexModel::builder().keys(doc! { "username": 1 }).build(),
+                    // Mongo drops sessions once their refresh token has expired.
+                    IndexModel::builder()
+                        .keys(doc! { "expires_at": 1 })
+                        .options(
+                            IndexOptions::builder()
+                                .expire_after(Duration::from_secs(0))
+                                .build(),
+                        )
+                        .build(),
+                ],
+                None,
+            )
+            .await?;
+        Ok(())
+    }
+
+    pub async fn register_user(&self, mut user_data: UserRegister) -> Result<()> {
+        let filter = doc! { "gmail": &user_data.gmail };
+        if self.user_collection.find_one(filter, None).await?.is_some() {
+            return Err(Error::UserWithMailExists);
+        }
+
+        user_data.password = hash_password(&user_data.password)?;
+        user_data.role = Role::User;
+        self.user_collection.insert_one(user_data, None).await?;
+        Ok(())
+    }
+
+    pub async fn update_password_hash(&self, username: &str, password_hash: &str) -> Result<()> {
+        let result = self
+            .user_collection
+            .update_one(
+                doc! { "username": username },
+                doc! { "$set": { "password": password_hash } },
+                None,
+            )
+            .await?;
+
+        if result.matched_count == 0 {
+            return Err(Error::UserNotFound);
+        }
+
+        Ok(())
+    }
+    pub async fn find_user_by_username(&self, username: &str) -> Result<Option<UserRegister>> {
+        let user = self
+            .user_collection
+            .find_one(doc! { "username": username }, None)
+            .await?;
+
+        Ok(user)
+    }
+
+    pub async fn add_post(&self, post: MissingPost) -> Result<ObjectId> {
+        let insert_result = self.post_collection.insert_one(post, None).await?;
+        insert_result
+            .inserted_id
+            .as_object_id()
+            .ok_or(Error::DatabaseError("Failed to get inserted ID".into()))
+    }
+
+    pub async fn getallpost(&self, filter: &PostFilter, page: &PageRequest) -> Result<PostPage> {
+        let limit = page.limit();
+        let mut pipeline = vec![doc! { "$match": filter.to_document()? }];
+        if let Some(after) = page.cursor_filter()? {
+            pipeline.push(doc! { "$match": after });
+        }
+        pipeline.push(doc! { "$sort": page.sort_document() });
+        // One extra post tells us whether there is another page.
+        pipeline.push(doc! { "$limit": limit + 1 });
+
+        // Decide on the next page before dropping posts whose author is
+        // gone, so an orphaned post can't end the feed early.
+        let mut posts = self.posts_with_authors(pipeline).await?;
+        let next_cursor = if posts.len() as i64 > limit {
+            posts.truncate(limit as usize);
+            posts.last().and_then(|(post, _)| page.encode_cursor(post))
+        } else {
+            None
+        };
+
+        Ok(PostPage {
+            posts: posts
+                .into_iter()
+                .filter_map(|(post, user)| Some(PostWithUser::new(post, user?)))
+                .collect(),
+            next_cursor,
+        })
+    }
+
+    /// Runs `pipeline` over the posts collection and joins each post's
+    /// author in the same query. The author is `None` when their account
+    /// no longer exists; callers decide whether to drop the post.
+    async fn posts_with_authors(
+        &self,
+        mut pipeline: Vec<Document>,
+    ) -> Result<Vec<(MissingPost, Option<UserPublic>)>> {
+        pipeline.extend(self.author_lookup());
+
+        let mut cursor = self.post_collection.aggregate(pipeline, None).await?;
+        let mut posts = Vec::new();
+        while let Some(document) = cursor.try_next().await? {
+            posts.push(split_author(document)?);
+        }
+
+        Ok(posts)
+    }
+
+    /// Aggregation stages that attach the post's author as `author`. Posts
+    /// whose author no longer exists are kept, without `author`.
+    fn author_lookup(&self) -> [Document; 3] {
+        [
+            doc! {
+                "$lookup": {
+                    "from": self.user_collection.name(),
+                    "localField": "user",
+                    "foreignField": "username",
+                    "as": "author",
+                }
+            },
+            doc! { "$unwind": { "path": "$author", "preserveNullAndEmptyArrays": true } },
+            doc! { "$project": { "author.password": 0 } },
+        ]
+    }
+
+    /// Open posts of the opposite kind, by other users, whose category does
+    /// not rule them out as the same item.
+    pub async fn match_candidates(&self, post: &MissingPost) -> Result<Vec<MissingPost>> {
+        let opposite = match post.kind {
+            PostKind::Lost => PostKind::Found,
+            PostKind::Found => PostKind::Lost,
+        };
+        let mut filter = PostFilter {
+            kind: Some(opposite),
+            status: Some(PostStatus::Open),
+            ..Default::default()
+        }
+        .to_document()?;
+        filter.insert("user", doc! { "$ne": &post.user });
+        if post.category != Category::Other {
+            filter.insert("category", doc! { "$in": [post.category.as_str(), "other", null] });
+        }
+
+        let options = FindOptions::builder()
+            .sort(doc! { "_id": -1 })
+            .limit(MAX_MATCH_CANDIDATES)
+            .build();
+        let cursor = self.post_collection.find(filter, options).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    /// Replaces the stored matches of `post` with `scored`.
+    pub async fn save_matches(
+        &self,
+        post: &MissingPost,
+        scored: Vec<(ObjectId, ScoreBreakdown)>,
+    ) -> Result<()> {
+        let post_id = post.id.ok_or(Error::PostNotFound)?;
+        let (own_field, other_field) = match_fields(post.kind);
+        let now = timestamp::to_bson(&Utc::now());
+
+        let mut kept = Vec::with_capacity(scored.len());
+        for (candidate, breakdown) in scored {
+            let mut filter = Document::new();
+            filter.insert(own_field, post_id);
+            filter.insert(other_field, candidate);
+            let update = doc! {
+                "$set": {
+                    "score": breakdown.total(),
+                    "breakdown": to_bson(&breakdown).map_err(|_| Error::Custom)?,
+                    "updated_at": now,
+                }
+            };
+            self.match_collection
+                .update_one(filter, update, UpdateOptions::builder().upsert(true).build())
+                .await?;
+            kept.push(candidate);
+        }
+
+        let mut stale = Document::new();
+        stale.insert(own_field, post_id);
+        stale.insert(other_field, doc! { "$nin": kept });
+        self.match_collection.delete_many(stale, None).await?;
+        Ok(())
+    }
+
+    /// Best matches for a post, for its author (or an admin). Candidates
+    /// that were deleted or resolved since scoring are skipped.
+    pub async fn get_matches(&self, post_id: &str, actor: &str) -> Result<Vec<MatchResult>> {
+        let post = self.find_live_post(post_id).await?;
+        if post.user != actor && !self.is_admin(actor).await? {
+            return Err(Error::Forbidden);
+        }
+        let post_id = post.id.ok_or(Error::PostNotFound)?;
+        let (own_field, _) = match_fields(post.kind);
+
+        let mut filter = Document::new();
+        filter.insert(own_field, post_id);
+        let options = FindOptions::builder()
+            .sort(doc! { "score": -1 })
+            .limit(MAX_MATCHES_SHOWN)
+            .build();
+        let matches: Vec<PostMatch> = self
+            .match_collection
+            .find(filter, options)
+            .await?
+            .try_collect()
+            .await?;
+
+        let other_ids: Vec<ObjectId> = matches
+            .iter()
+            .map(|m| match post.kind {
+                PostKind::Lost => m.found_post,
+                PostKind::Found => m.lost_post,
+            })
+            .collect();
+        let pipeline = vec![doc! {
+            "$match": {
+                "_id": { "$in": &other_ids },
+                "deleted_at": null,
+                "status": { "$nin": ["returned", "closed"] },
+            }
+        }];
+        let mut posts: HashMap<ObjectId, PostWithUser> = self
+            .posts_with_authors(pipeline)
+            .await?
+            .into_iter()
+            .filter_map(|(post, user)| Some((post.id?, PostWithUser::new(post, user?))))
+            .collect();
+
+        Ok(matches
+            .into_iter()
+            .zip(other_ids)
+            .filter_map(|(m, other_id)| {
+                Some(MatchResult {
+                    post: posts.remove(&other_id)?,
+                    score: m.score,
+                    breakdown: m.breakdown,
+                })
+            })
+            .collect())
+    }
+
+    /// Full-text search over description and place, best matches first.
+    pub async fn search_posts(
+        &self,
+        query: &str,
+        filter: &PostFilter,
+        limit: i64,
+    ) -> Result<Vec<(PostWithUser, f64)>> {
+        let mut matcher = filter.to_document()?;
+        matcher.insert("$text", doc! { "$search": query });
+
+        let mut pipeline = vec![
+            doc! { "$match": matcher },
+            doc! { "$addFields": { "score": { "$meta": "textScore" } } },
+            doc! { "$sort": { "score": -1, "_id": -1 } },
+            doc! { "$limit": limit },
+        ];
+        pipeline.extend(self.author_lookup());
+
+        let mut cursor = self.post_collection.aggregate(pipeline, None).await?;
+        let mut results = Vec::new();
+        while let Some(document) = cursor.try_next().await? {
+            let score = document.get_f64("score").unwrap_or_default();
+            if let (post, Some(author)) = split_author(document)? {
+                results.push((PostWithUser::new(post, author), score));
+            }
+        }
+
+        Ok(results)
+    }
+
+    pub async fn edit_username(&self, user: &UserRegister, gmail: &str) -> Result<()> {
+        let user_id = match &user.id {
+            Some(id) => id,
+            None => return Err(Error::UserNotFound),
+        };
+
+        let existing_user = self
+            .user_collection
+            .find_one(doc! { "gmail": gmail }, None)
+            .await?;
+
+        if existing_user.is_some() {
+            return Err(Error::UserWithMailExists);
+        }
+        let filter = doc! { "_id": user_id };
+        let update = doc! {
+            "$set": { "gmail": &gmail }
+        };
+
+        let result = self
+            .user_collection
+            .update_one(filter, update, None)
+            .await?;
+
+        if result.matched_count == 0 {
+            return Err(Error::UserNotFound);
+        }
+
+        Ok(())
+    }
+
+    pub async fn changephonenumber(&self, user: &UserRegister, phone_number: &str) -> Result<()> {
+        let user_id = match &user.id {
+            Some(id) => id,
+            None => return Err(Error::UserNotFound),
+        };
+
+        let filter = doc! { "_id": user_id };
+        let update = doc! {
+            "$set": { "PhoneNumber": &phone_number }
+        };
+
+        let result = self
+            .user_collection
+            .update_one(filter, update, None)
+            .await?;
+
+        if result.matched_count == 0 {
+            return Err(Error::UserNotFound);
+        }
+
+        Ok(())
+    }
+    pub async fn get_posts_by_user(&self, username: &str) -> Result<Vec<PostWithUser>> {
+        let pipeline = vec![
+            doc! { "$match": { "user": username, "deleted_at": null } },
+            doc! { "$sort": { "_id": -1 } },
+        ];
+
+        let posts = self.posts_with_authors(pipeline).await?;
+        Ok(posts
+            .into_iter()
+            .filter_map(|(post, user)| Some(PostWithUser::new(post, user?)))
+            .collect())
+    }
+
+    pub async fn add_comment_to_post(&self, post_id: &str, comment: Comment) -> Result<()> {
+        let obj_id = ObjectId::parse_str(post_id).map_err(|_| Error::UserNotFound)?;
+
+        let filter = doc! { "_id": obj_id, "deleted_at": null };
+        let update = doc! {
+            "$push": { "comments": to_bson(&comment).map_err(|_| Error::UserNotFound )? }
+        };
+
+        let result = self
+            .post_collection
+            .update_one(filter, update, None)
+            .await?;
+
+        if result.matched_count == 0 {
+            return Err(Error::PostNotFound);
+        }
+
+        Ok(())
+    }
+
+    pub async fn getallcomments(&self, post_id: String) -> Result<Vec<Comment>> {
+        let object_id = match mongodb::bson::oid::ObjectId::parse_str(&post_id) {
+            Ok(oid) => oid,
+            Err(_) => return Err(Error::PostNotFound),
+        };
+        let post = self
+            .post_collection
+            .find_one(doc! { "_id": object_id, "deleted_at": null }, None)
+            .await?
+            .ok_or(Error::PostNotFound)?;
+        Ok(post.comments)
+    }
+
+    /// Finds or opens the room between two users, about `post_id` when
+    /// given. A new room about a post needs the post to be live and must
+    /// include its author.
+    pub async fn create_or_get_chat(
+        &self,
+        user1: String,
+        user2: String,
+        post_id: Option<&str>,
+    ) -> Result<ChatRoom> {
+        let user1_exists = self
+            .user_collection
+            .find_one(doc! { "username": &user1 }, None)
+            .await?;
+        if user1_exists.is_none() {
+            return Err(Error::UserNotFound);
+        }
+
+        let user2_exists = self
+            .user_collection
+            .find_one(doc! { "username": &user2 }, None)
+            .await?;
+        if user2_exists.is_none() {
+            return Err(Error::UserNotFound);
+        }
+
+        let post_id = post_id
+            .map(|id| ObjectId::parse_str(id).map_err(|_| Error::InvalidId))
+            .transpose()?;
+
+        let filter = doc! {
+            "$or": [
+                { "user1": &user1, "user2": &user2 },
+                { "user1": &user2, "user2": &user1 }
+            ],
+            "post_id": post_id,
+        };
+        if let Some(chat) = self.chat_room_collection.find_one(filter, None).await? {
+            println!("Found existing chat with ID: {:?}", chat.id);
+       
+            return Ok(chat);
+        }
+        // Existing rooms outlive their post; only new ones need it live.
+        if let Some(post_id) = post_id {
+            let post = self.find_live_post(&post_id.to_hex()).await?;
+            if post.user != user1 && post.user != user2 {
+                return Err(Error::Forbidden);
+            }
+        }
+        let now = Utc::now();
+        let chat_room = ChatRoom {
+            id: None,
+            user1,
+            user2,
+            created_at: now,
+            post_id,
+        };
+        let insert_result = self
+            .chat_room_collection
+            .insert_one(&chat_room, None)
+            .await?;
+        let id = insert_result
+            .inserted_id
+            .as_object_id()
+            .ok_or(Error::DatabaseError(String::from(
+                "Failed to get inserted ID",
+            )))?;
+
+        Ok(ChatRoom {
+            id: Some(id),
+            ..chat_room
+        })
+    }
+    pub async fn send_message(
+        &self,
+        chat_id: &str,
+        sender: String,
+        message: String,
+    ) -> Result<ChatMessage> {
+        let chat_obj_id = ObjectId::parse_str(chat_id).map_err(|_| Error::InvalidToken)?;
+        let chat = self
+            .chat_room_collection
+            .find_one(doc! { "_id": &chat_obj_id }, None)
+            .await?
+            .ok_or(Error::ChatNotFound)?;
+
+        if chat.user1 != sender && chat.user2 != sender {
+            return Err(Error::Unauthorized);
+        }
+        if message.trim().is_empty() {
+            return Err(Error::InvalidInput("Message is empty".to_string()));
+        }
+
+        let now = Utc::now();
+        let chat_message = ChatMessage {
+            id: None,
+            char_id: chat_id.to_string(),
+            sender,
+            message,
+            send_at: now,
+            is_read: false,
+        };
+
+        let insert_result = self.chat_message_collection.insert_one(&chat_message, None).await?;
+        let id = insert_result
+            .inserted_id
+            .as_object_id()
+            .ok_or(Error::DatabaseError("Failed to get inserted ID".into()))?;
+
+        let chat_message = ChatMessage {
+            id: Some(id),
+            ..chat_message
+        };
+        self.hub.publish(
+            &[&chat.user1, &chat.user2],
+            ChatEvent::Message {
+                room_id: chat_id.to_string(),
+                message: chat_message.clone(),
+            },
+        );
+        Ok(chat_message)
+    }
+    /// One page of a conversation, oldest first. See [`MessageQuery`] for
+    /// the paging modes.
+    pub async fn get_chat_messages(&self, chat_id: &str, query: &MessageQuery) -> Result<MessagePage> {
+        let limit = query.limit();
+        let (position, forward) = query.position()?;
+
+        let mut filter = doc! { "char_id": chat_id };
+        if let Some(position) = position {
+            filter.extend(position.filter(forward));
+        }
+        let order = if forward { 1 } else { -1 };
+        let options = FindOptions::builder()
+            .sort(doc! { "send_at": order, "_id": order })
+            .limit(limit + 1)
+            .build();
+        let cursor = self.chat_message_collection.find(filter, options).await?;
+        let mut messages: Vec<ChatMessage> = cursor.try_collect().await?;
+
+        let has_more = messages.len() as i64 > limit;
+        messages.truncate(limit as usize);
+        let next_cursor = if has_more {
+            messages.last().and_then(|last| encode_message_cursor(last, forward))
+        } else {
+            None
+        };
+        if !forward {
+            messages.reverse();
+        }
+
+        Ok(MessagePage { messages, next_cursor })
+    }
+    /// Marks the other participant's messages in the room as read, up to
+    /// and including `message_id`, and tells both sides over the hub.
+    /// Returns how many messages changed.
+    pub async fn mark_chat_read(&self, room_id: &str, reader: &str, message_id: &str) -> Result<u64> {
+        let room_obj_id = ObjectId::parse_str(room_id).map_err(|_| Error::InvalidId)?;
+        let message_id = ObjectId::parse_str(message_id).map_err(|_| Error::InvalidId)?;
+        let chat = self
+            .chat_room_collection
+            .find_one(doc! { "_id": room_obj_id }, None)
+            .await?
+            .ok_or(Error::ChatNotFound)?;
+        if chat.user1 != reader && chat.user2 != reader {
+            return Err(Error::Forbidden);
+        }
+
+        let room_id = room_obj_id.to_hex();
+        let up_to = self
+            .chat_message_collection
+            .find_one(doc! { "_id": message_id, "char_id": &room_id }, None)
+            .await?
+            .ok_or(Error::MessageNotFound)?;
+        let up_to_at = timestamp::to_bson(&up_to.send_at);
+
+        let result = self
+            .chat_message_collection
+            .update_many(
+                doc! {
+                    "char_id": &room_id,
+                    "sender": { "$ne": reader },
+                    "is_read": false,
+                    "$or": [
+                        { "send_at": { "$lt": &up_to_at } },
+                        { "send_at": &up_to_at, "_id": { "$lte": message_id } },
+                    ],
+                },
+                doc! { "$set": { "is_read": true } },
+                None,
+            )
+            .await?;
+
+        if result.modified_count > 0 {
+            self.hub.publish(
+                &[&chat.user1, &chat.user2],
+                ChatEvent::Read {
+                    room_id,
+                    reader: reader.to_string(),
+                    up_to: message_id.to_hex(),
+                },
+            );
+        }
+        Ok(result.modified_count)
+    }
+
+    /// Unread messages addressed to `username`, keyed by room ID. Rooms
+    /// with nothing unread are left out.
+    pub async fn unread_counts(&self, username: &str) -> Result<HashMap<String, u64>> {
+        let room_ids = room_ids(&self.get_chat_rooms(username).await?);
+        self.unread_counts_in(username, &room_ids).await
+    }
+
+    async fn unread_counts_in(&self, username: &str, room_ids: &[String]) -> Result<HashMap<String, u64>> {
+        if room_ids.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        let pipeline = vec![
+            doc! { "$match": {
+                "char_id": { "$in": room_ids },
+                "sender": { "$ne": username },
+                "is_read": false,
+            } },
+            doc! { "$group": { "_id": "$char_id", "count": { "$sum": 1 } } },
+        ];
+        let mut cursor = self.chat_message_collection.aggregate(pipeline, None).await?;
+        let mut counts = HashMap::new();
+        while let Some(row) = cursor.try_next().await? {
+            if let (Ok(room_id), Ok(count)) = (row.get_str("_id"), row.get_i32("count")) {
+                counts.insert(room_id.to_string(), count as u64);
+            }
+        }
+        Ok(counts)
+    }
+
+    /// Newest message of each room, keyed by room ID.
+    async fn last_messages(&self, room_ids: &[String]) -> Result<HashMap<String, ChatMessage>> {
+        if room_ids.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        let pipeline = vec![
+            doc! { "$match": { "char_id": { "$in": room_ids } } },
+            doc! { "$sort": { "char_id": 1, "send_at": -1, "_id": -1 } },
+            doc! { "$group": { "_id": "$char_id", "message": { "$first": "$$ROOT" } } },
+        ];
+        let mut cursor = self.chat_message_collection.aggregate(pipeline, None).await?;
+        let mut messages = HashMap::new();
+        while let Some(row) = cursor.try_next().await? {
+            let message = row
+                .get_document("message")
+                .map_err(|e| Error::DatabaseError(e.to_string()))?;
+            let message: ChatMessage = from_document(message.clone())
+                .map_err(|e| Error::DatabaseError(e.to_string()))?;
+            messages.insert(message.char_id.clone(), message);
+        }
+        Ok(messages)
+    }
+
+    /// Live posts by ID, summarised for room headers.
+    pub async fn post_summaries(&self, ids: &[ObjectId]) -> Result<HashMap<ObjectId, PostSummary>> {
+        if ids.is_empty() {
+            return Ok(HashMap::new());
+        }
+        let mut cursor = self
+            .post_collection
+            .find(doc! { "_id": { "$in": ids }, "deleted_at": null }, None)
+            .await?;
+        let mut summaries = HashMap::new();
+        while let Some(post) = cursor.try_next().await? {
+            if let Some(summary) = PostSummary::from_post(post) {
+                summaries.insert(summary.id, summary);
+            }
+        }
+        Ok(summaries)
+    }
+
+    /// The user's rooms for the Chats tab, with the other participant's
+    /// profile, the post it is about, the last message and the unread
+    /// count, most recently active first.
+    pub async fn get_chat_room_summaries(&self, username: &str) -> Result<Vec<ChatRoomSummary>> {
+        let rooms = self.get_chat_rooms(username).await?;
+        self.summarize_rooms(username, rooms).await
+    }
+
+    /// Every conversation about a post, for its author.
+    pub async fn get_post_chat_rooms(&self, post_id: &str, username: &str) -> Result<Vec<ChatRoomSummary>> {
+        let post = self.find_live_post(post_id).await?;
+        if post.user != username {
+            return Err(Error::Forbidden);
+        }
+        let rooms: Vec<ChatRoom> = self
+            .chat_room_collection
+            .find(doc! { "post_id": post.id }, None)
+            .await?
+            .try_collect()
+            .await?;
+        self.summarize_rooms(username, rooms).await
+    }
+
+    async fn summarize_rooms(&self, username: &str, rooms: Vec<ChatRoom>) -> Result<Vec<ChatRoomSummary>> {
+        let room_ids = room_ids(&rooms);
+        let counts = self.unread_counts_in(username, &room_ids).await?;
+        let mut last_messages = self.last_messages(&room_ids).await?;
+
+        let peer_names: Vec<&str> = rooms.iter().map(|room| room.peer_of(username)).collect();
+        let mut peers: HashMap<String, UserPublic> = HashMap::new();
+        let mut cursor = self
+            .user_collection
+            .find(doc! { "username": { "$in": &peer_names } }, None)
+            .await?;
+        while let Some(user) = cursor.try_next().await? {
+            peers.insert(user.username.clone(), UserPublic::from(user));
+        }
+        let post_ids: Vec<ObjectId> = rooms.iter().filter_map(|room| room.post_id).collect();
+        let posts = self.post_summaries(&post_ids).await?;
+
+        let mut summaries: Vec<ChatRoomSummary> = rooms
+            .into_iter()
+            .map(|room| {
+                let room_id = room.id.map(|id| id.to_hex()).unwrap_or_default();
+                let peer = peers.get(room.peer_of(username)).cloned();
+                let post = room.post_id.and_then(|id| posts.get(&id).cloned());
+                let last_message = last_messages.remove(&room_id).map(ChatPreview::from);
+                let last_activity = last_message
+                    .as_ref()
+                    .map_or(room.created_at, |message| message.send_at);
+                ChatRoomSummary {
+                    unread_count: counts.get(&room_id).copied().unwrap_or(0),
+                    room,
+                    peer,
+                    post,
+                    last_message,
+                    last_activity,
+                }
+            })
+            .collect();
+        summaries.sort_by_key(|summary| std::cmp::Reverse(summary.last_activity));
+        Ok(summaries)
+    }
+
+    pub async fn get_chat_rooms(&self, username: &str) -> Result<Vec<ChatRoom>> {
+        let filter = doc! {
+            "$or": [
+                { "user1": username },
+                { "user2": username }
+            ]
+        };
+
+        let mut cursor = self.chat_room_collection.find(filter, None).await?;
+        let mut chat_rooms = Vec::new();
+
+        while let Some(chat_room) = cursor.try_next().await? {
+            chat_rooms.push(chat_room);
+        }
+
+        Ok(chat_rooms)
+    }
+
+    /// Loads a post that has not been deleted.
+    pub async fn find_live_post(&self, post_id: &str) -> Result<MissingPost> {
+        let post_id = ObjectId::parse_str(post_id).map_err(|_| Error::InvalidId)?;
+        self.post_collection
+            .find_one(doc! { "_id": post_id, "deleted_at": null }, None)
+            .await?
+            .ok_or(Error::PostNotFound)
+    }
+
+    pub async fn update_post(&self, post_id: &str, actor: &str, update: PostUpdate) -> Result<ObjectId> {
+        let post = self.find_live_post(post_id).await?;
+        if post.user != actor {
+            return Err(Error::Forbidden);
+        }
+        let post_id = post.id.ok_or(Error::PostNotFound)?;
+
+        let mut set = Document::new();
+        if let Some(kind) = update.kind {
+            set.insert("kind", to_bson(&kind).map_err(|_| Error::Custom)?);
+        }
+        if let Some(category) = update.category {
+            set.insert("category", category.as_str());
+        }
+        if let Some(attributes) = update.attributes {
+            let attributes = attributes.validated()?;
+            set.insert("attributes", to_bson(&attributes).map_err(|_| Error::Custom)?);
+        }
+        if let Some(description) = update.description {
+            set.insert("description", description);
+        }
+        if let Some(date) = update.date {
+            set.insert("date", timestamp::to_bson(&validate_post_date(date)?));
+        }
+        if let Some(place) = update.place {
+            set.insert("place", place);
+        }
+        let location = match update.location_id {
+            // An empty id detaches the post from the directory.
+            Some(location_id) if location_id.is_empty() => {
+                set.insert("location_id", Bson::Null);
+                Some(None)
+            }
+            Some(location_id) => {
+                let location = self.find_location(&location_id).await?;
+                set.insert("location_id", location.id);
+                Some(Some(location))
+            }
+            None => None,
+        };
+        // Explicit coordinates win; otherwise a derived (or missing) point
+        // follows the new location.
+        let geo = match (update.coordinates, location) {
+            (Some(coordinates), _) => Some(self.post_geo(Some(coordinates), None).await?),
+            (None, Some(location)) if post.geo_derived || post.geo.is_none() => {
+                Some(self.post_geo(None, location.as_ref()).await?)
+            }
+            _ => None,
+        };
+        if let Some((geo, derived)) = geo {
+            set.insert("geo", to_bson(&geo).map_err(|_| Error::Custom)?);
+            set.insert("geo_derived", derived);
+        }
+        match update.image_id {
+            Some(image_id) => {
+                let upload = self.find_upload(&image_id, actor).await?;
+                set.insert("image_id", upload.id);
+                set.insert("image_link", upload.url());
+                set.insert("image_hash", upload.image_hash);
+            }
+            None => {
+                if let Some(image_link) = update.image_link {
+                    // A linked photo replaces the uploaded one.
+                    set.insert("image_id", Bson::Null);
+                    set.insert("image_hash", Bson::Null);
+                    set.insert("image_link", image_link);
+                }
+            }
+        }
+        let kind = update.kind.unwrap_or(post.kind);
+        match update.verification_questions {
+            Some(questions) => {
+                set.insert("verification_questions", validate_questions(kind, questions)?);
+            }
+            // Lost posts cannot keep questions from when they were marked found.
+            None if kind == PostKind::Lost && !post.verification_questions.is_empty() => {
+                set.insert("verification_questions", Vec::<String>::new());
+            }
+            None => {}
+        }
+        if set.is_empty() {
+            return Err(Error::InvalidInput("Nothing to update".to_string()));
+        }
+
+        self.post_collection
+            .update_one(
+                doc! { "_id": post_id, "deleted_at": null },
+                doc! { "$set": set },
+                None,
+            )
+            .await?;
+        Ok(post_id)
+    }
+
+    /// Soft-deletes a post. The author or an admin may remove it; the
+    /// document is kept for moderation.
+    pub async fn delete_post(&self, post_id: &str, actor: &str) -> Result<()> {
+        let post = self.find_live_post(post_id).await?;
+        if post.user != actor && !self.is_admin(actor).await? {
+            return Err(Error::Forbidden);
+        }
+
+        self.post_collection
+            .update_one(
+                doc! { "_id": post.id, "deleted_at": null },
+                doc! {
+                    "$set": {
+                        "deleted_at": DateTime::now(),
+                        "deleted_by": actor,
+                    }
+                },
+                None,
+            )
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_deleted_posts(&self) -> Result<Vec<MissingPost>> {
+        let options = FindOptions::builder().sort(doc! { "deleted_at": -1 }).build();
+        let cursor = self
+            .post_collection
+            .find(doc! { "deleted_at": { "$ne": null } }, options)
+            .await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    pub async fn is_admin(&self, username: &str) -> Result<bool> {
+        let user = self.find_user_by_username(username).await?;
+        Ok(user.is_some_and(|user| user.role == Role::Admin))
+    }
+
+    /// Moves a post to `next`, recording who did it and when. Only the
+    /// author or an admin may change the status. A post only becomes
+    /// claimed through an accepted claim, and a claimed post is only
+    /// returned through its handover.
+    pub async fn update_post_status(
+        &self,
+        post_id: &str,
+        actor: &str,
+        next: PostStatus,
+    ) -> Result<StatusChange> {
+        let post = self.find_live_post(post_id).await?;
+        let post_id = post.id.ok_or(Error::PostNotFound)?;
+
+        if post.user != actor && !self.is_admin(actor).await? {
+            return Err(Error::Forbidden);
+        }
+        if !post.status.can_transition_to(next) {
+            return Err(Error::InvalidStatusTransition);
+        }
+        // A claimed post is returned by confirming its handover, which keeps
+        // the claim and the return stats in step.
+        if post.status == PostStatus::Claimed && next == PostStatus::Returned {
+            return Err(Error::InvalidStatusTransition);
+        }
+        // Only accepting a claim puts a post in claimed.
+        if next == PostStatus::Claimed {
+            let accepted = doc! { "post_id": post_id, "status": ClaimStatus::Accepted.as_str() };
+            if self.claim_collection.count_documents(accepted, None).await? == 0 {
+                return Err(Error::InvalidStatusTransition);
+            }
+        }
+
+        let change = self.set_post_status(&post, actor, next).await?;
+        if change.from == PostStatus::Claimed && matches!(next, PostStatus::Open | PostStatus::Closed) {
+            self.release_accepted_claim(&post_id).await?;
+        }
+        Ok(change)
+    }
+
+    /// Records a checked status change, guarded on the status the caller
+    /// saw.
+    async fn set_post_status(&self, post: &MissingPost, actor: &str, next: PostStatus) -> Result<StatusChange> {
+        let post_id = post.id.ok_or(Error::PostNotFound)?;
+        let change = StatusChange {
+            from: post.status,
+            to: next,
+            by: actor.to_string(),
+            at: Utc::now(),
+        };
+        let result = self
+            .post_collection
+            .update_one(
+                // Guard on the status we checked so concurrent moves can't both win.
+                doc! { "_id": post_id, "status": post.status.filter_value() },
+                doc! {
+                    "$set": { "status": next.as_str() },
+                    "$push": {
+                        "status_history": {
+                            "from": change.from.as_str(),
+                            "to": change.to.as_str(),
+                            "by": &change.by,
+                            "at": timestamp::to_bson(&change.at),
+                        }
+                    },
+                },
+                None,
+            )
+            .await?;
+
+        if result.matched_count == 0 {
+            return Err(Error::InvalidStatusTransition);
+        }
+
+        Ok(change)
+    }
+
+    fn refresh_token_expiry(now: DateTime) -> DateTime {
+        let lifetime_ms = config().auth.refresh_token_lifetime_days * 24 * 60 * 60 * 1000;
+        DateTime::from_millis(now.timestamp_millis() + lifetime_ms)
+    }
+
+    pub async fn create_session(&self, username: &str) -> Result<IssuedSession> {
+        let refresh_token = generate_refresh_token();
+        let now = DateTime::now();
+        let session = Session {
+            id: None,
+            username: username.to_string(),
+            refresh_token_hash: hash_refresh_token(&refresh_token),
+            previous_token_hash: None,
+            created_at: now,
+            last_used_at: now,
+            expires_at: Self::refresh_token_expiry(now),
+            revoked_at: None,
+        };
+
+        let insert_result = self.session_collection.insert_one(&session, None).await?;
+        let session_id = insert_result
+            .inserted_id
+            .as_object_id()
+            .ok_or(Error::DatabaseError("Failed to get inserted ID".into()))?;
+
+        Ok(IssuedSession {
+            session_id,
+            username: session.username,
+            refresh_token,
+        })
+    }
+
+    /// Exchanges a refresh token for a new one. Presenting a token that has
+    /// already been rotated out means it leaked, so the whole session is
+    /// revoked.
+    pub async fn rotate_session(&self, refresh_token: &str) -> Result<IssuedSession> {
+        let presented_hash = hash_refresh_token(refresh_token);
+        let new_token = generate_refresh_token();
+        let now = DateTime::now();
+
+        let rotated = self
+            .session_collection
+            .find_one_and_update(
+                doc! {
+                    "refresh_token_hash": &presented_hash,
+                    "revoked_at": null,
+                    "expires_at": { "$gt": now },
+                },
+                doc! {
+                    "$set": {
+                        "refresh_token_hash": hash_refresh_token(&new_token),
+                        "previous_token_hash": &presented_hash,
+                        "last_used_at": now,
+                        "expires_at": Self::refresh_token_expiry(now),
+                    }
+                },
+                None,
+            )
+            .await?;
+
+        if let Some(session) = rotated {
+            return Ok(IssuedSession {
+                session_id: session.id.ok_or(Error::InvalidToken)?,
+                username: session.username,
+                refresh_token: new_token,
+            });
+        }
+
+        let reused = self
+            .session_collection
+            .find_one_and_update(
+                doc! { "previous_token_hash": &presented_hash, "revoked_at": null },
+                doc! { "$set": { "revoked_at": now } },
+                None,
+            )
+            .await?;
+        if let Some(session) = reused {
+            eprintln!(
+                "Refresh token reuse detected for {}, session {:?} revoked",
+                session.username, session.id
+            );
+        }
+
+        Err(Error::InvalidToken)
+    }
+
+    pub async fn is_session_active(&self, session_id: &str) -> Result<bool> {
+        let Ok(session_id) = ObjectId::parse_str(session_id) else {
+            return Ok(false);
+        };
+        let active = self
+            .session_collection
+            .count_documents(
+                // The TTL monitor deletes expired sessions only eventually.
+                doc! { "_id": session_id, "revoked_at": null, "expires_at": { "$gt": DateTime::now() } },
+                None,
+            )
+            .await?;
+        Ok(active > 0)
+    }
+
+    pub async fn revoke_session(&self, session_id: &str, username: &str) -> Result<()> {
+        let session_id = ObjectId::parse_str(session_id).map_err(|_| Error::InvalidToken)?;
+        self.session_collection
+            .update_one(
+                doc! { "_id": session_id, "username": username, "revoked_at": null },
+                doc! { "$set": { "revoked_at": DateTime::now() } },
+                None,
+            )
+            .await?;
+        Ok(())
+    }
+
+    pub async fn revoke_all_sessions(&self, username: &str) -> Result<u64> {
+        let result = self
+            .session_collection
+            .update_many(
+                doc! { "username": username, "revoked_at": null },
+                doc! { "$set": { "revoked_at": DateTime::now() } },
+                None,
+            )
+            .await?;
+        Ok(result.modified_count)
+    }
+
+    /// The questions a claimant must answer for a found post.
+    pub async fn get_verification_questions(&self, post_id: &str) -> Result<Vec<String>> {
+        let post = self.find_live_post(post_id).await?;
+        if post.kind != PostKind::Found {
+            return Err(Error::InvalidInput("Only found posts can be claimed".to_string()));
+        }
+        Ok(post.verification_questions)
+    }
+
+    pub async fn create_claim(
+        &self,
+        post_id: &str,
+        claimant: &str,
+        answers: Vec<String>,
+        message: Option<String>,
+    ) -> Result<Claim> {
+        let post = self.find_live_post(post_id).await?;
+        let post_id = post.id.ok_or(Error::PostNotFound)?;
+        if post.kind != PostKind::Found {
+            return Err(Error::InvalidInput("Only found posts can be claimed".to_string()));
+        }
+        if post.status != PostStatus::Open {
+            return Err(Error::InvalidStatusTransition);
+        }
+        if post.user == claimant {
+            return Err(Error::InvalidInput("You cannot claim your own post".to_string()));
+        }
+        if answers.len() != post.verification_questions.len() {
+            return Err(Error::InvalidInput(format!(
+                "Expected {} answers",
+                post.verification_questions.len()
+            )));
+        }
+        let answers: Vec<String> = answers.into_iter().map(|a| a.trim().to_string()).collect();
+        if answers.iter().any(|a| a.is_empty() || a.chars().count() > MAX_ANSWER_LEN) {
+            return Err(Error::InvalidInput(format!(
+                "Answers must be between 1 and {} characters",
+                MAX_ANSWER_LEN
+            )));
+        }
+
+        let pending = doc! {
+            "post_id": post_id,
+            "claimant": claimant,
+            "status": ClaimStatus::Pending.as_str(),
+        };
+        if self.claim_collection.find_one(pending, None).await?.is_some() {
+            return Err(Error::DuplicateClaim);
+        }
+
+        let claim = Claim {
+            id: None,
+            post_id,
+            claimant: claimant.to_string(),
+            finder: post.user,
+            answers: post
+                .verification_questions
+                .into_iter()
+                .zip(answers)
+                .map(|(question, answer)| ClaimAnswer { question, answer })
+                .collect(),
+            message: message
+                .map(|message| message.trim().to_string())
+                .filter(|message| !message.is_empty()),
+            status: ClaimStatus::Pending,
+            created_at: Utc::now(),
+            reviewed_at: None,
+        };
+        let insert_result = match self.claim_collection.insert_one(&claim, None).await {
+            Ok(insert_result) => insert_result,
+            Err(e) if is_duplicate_key(&e) => return Err(Error::DuplicateClaim),
+            Err(e) => return Err(e.into()),
+        };
+        let id = insert_result
+            .inserted_id
+            .as_object_id()
+            .ok_or(Error::DatabaseError("Failed to get inserted ID".into()))?;
+
+        Ok(Claim {
+            id: Some(id),
+            ..claim
+        })
+    }
+
+    /// The finder's review queue for one of their posts, oldest first.
+    pub async fn get_post_claims(
+        &self,
+        post_id: &str,
+        finder: &str,
+        status: Option<ClaimStatus>,
+    ) -> Result<Vec<Claim>> {
+        let post = self.find_live_post(post_id).await?;
+        if post.user != finder {
+            return Err(Error::Forbidden);
+        }
+
+        let mut filter = doc! { "post_id": post.id };
+        if let Some(status) = status {
+            filter.insert("status", status.as_str());
+        }
+        let options = FindOptions::builder().sort(doc! { "_id": 1 }).build();
+        let cursor = self.claim_collection.find(filter, options).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    pub async fn get_claims_by_claimant(&self, claimant: &str) -> Result<Vec<Claim>> {
+        let options = FindOptions::builder().sort(doc! { "_id": -1 }).build();
+        let cursor = self
+            .claim_collection
+            .find(doc! { "claimant": claimant }, options)
+            .await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    pub async fn find_claim(&self, claim_id: &str) -> Result<Claim> {
+        let claim_id = ObjectId::parse_str(claim_id).map_err(|_| Error::InvalidId)?;
+        self.claim_collection
+            .find_one(doc! { "_id": claim_id }, None)
+            .await?
+            .ok_or(Error::ClaimNotFound)
+    }
+
+    /// Accepts or rejects a pending claim. Accepting moves the post to
+    /// claimed, so only one claim per post can be accepted at a time, and
+    /// opens a handover for the claimant.
+    pub async fn review_claim(&self, claim_id: &str, finder: &str, accept: bool) -> Result<Claim> {
+        let claim = self.find_claim(claim_id).await?;
+        let id = claim.id.ok_or(Error::ClaimNotFound)?;
+        if claim.finder != finder {
+            return Err(Error::Forbidden);
+        }
+        if claim.status != ClaimStatus::Pending {
+            return Err(Error::InvalidStatusTransition);
+        }
+
+        // Settle the claim first: of two concurrent reviews only the one
+        // whose update matches goes on to touch the post.
+        let status = if accept { ClaimStatus::Accepted } else { ClaimStatus::Rejected };
+        let reviewed_at = Utc::now();
+        let result = self
+            .claim_collection
+            .update_one(
+                doc! { "_id": id, "status": ClaimStatus::Pending.as_str() },
+                doc! {
+                    "$set": {
+                        "status": status.as_str(),
+                        "reviewed_at": timestamp::to_bson(&reviewed_at),
+                    }
+                },
+                None,
+            )
+            .await?;
+        if result.matched_count == 0 {
+            return Err(Error::InvalidStatusTransition);
+        }
+
+        let post_change = if accept {
+            match self
+                .update_post_status(&claim.post_id.to_hex(), finder, PostStatus::Claimed)
+                .await
+            {
+                Ok(change) => Some(change),
+                Err(e) => {
+                    // Another claim got the post first; leave this one for review.
+                    self.return_claim_to_review(&id).await?;
+                    return Err(e);
+                }
+            }
+        } else {
+            None
+        };
+
+        let claim = Claim {
+            status,
+            reviewed_at: Some(reviewed_at),
+            ..claim
+        };
+        if let Some(change) = post_change
+            && let Err(e) = self.create_handover(&claim).await
+        {
+            // Without a handover the post could never be returned; undo the
+            // acceptance so the finder can review the claim again.
+            self.undo_status_change(&claim.post_id, &change).await?;
+            self.return_claim_to_review(&id).await?;
+            return Err(e);
+        }
+        Ok(claim)
+    }
+
+    /// Puts an accepted claim back in the finder's review queue.
+    async fn return_claim_to_review(&self, claim_id: &ObjectId) -> Result<()> {
+        self.claim_collection
+            .update_one(
+                doc! { "_id": claim_id, "status": ClaimStatus::Accepted.as_str() },
+                doc! {
+                    "$set": { "status": ClaimStatus::Pending.as_str() },
+                    "$unset": { "reviewed_at": "" },
+                },
+                None,
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Reverts a status change whose operation failed later on, removing
+    /// it from the post's history as well.
+    async fn undo_status_change(&self, post_id: &ObjectId, change: &StatusChange) -> Result<()> {
+        self.post_collection
+            .update_one(
+                doc! { "_id": post_id, "status": change.to.as_str() },
+                doc! {
+                    "$set": { "status": change.from.as_str() },
+                    "$pop": { "status_history": 1 },
+                },
+                None,
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Called when a post leaves claimed other than through its handover:
+    /// voids the pending handover so its code can't return the post later,
+    /// and rejects the claim it belonged to.
+    async fn release_accepted_claim(&self, post_id: &ObjectId) -> Result<()> {
+        self.handover_collection
+            .update_many(
+                doc! { "post_id": post_id, "status": HandoverStatus::Pending.as_str() },
+                doc! {
+                    "$set": { "status": HandoverStatus::Voided.as_str() },
+                    "$unset": { "code": "" },
+                },
+                None,
+            )
+            .await?;
+        self.claim_collection
+            .update_many(
+                doc! { "post_id": post_id, "status": ClaimStatus::Accepted.as_str() },
+                doc! {
+                    "$set": {
+                        "status": ClaimStatus::Rejected.as_str(),
+                        "reviewed_at": timestamp::to_bson(&Utc::now()),
+                    }
+                },
+                None,
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn create_handover(&self, claim: &Claim) -> Result<Handover> {
+        let handover = Handover {
+            id: None,
+            claim_id: claim.id.ok_or(Error::ClaimNotFound)?,
+            post_id: claim.post_id,
+            owner: claim.claimant.clone(),
+            finder: claim.finder.clone(),
+            code: Some(generate_handover_code()),
+            status: HandoverStatus::Pending,
+            category: None,
+            created_at: Utc::now(),
+            completed_at: None,
         };
+        let insert_result = self.handover_collection.insert_one(&handover, None).await?;
+        let id = insert_result
+            .inserted_id
+            .as_object_id()
+            .ok_or(Error::DatabaseError("Failed to get inserted ID".into()))?;
 
-        let result = self
-            .post_collection
-            .update_one(filter, update, None)
+        Ok(Handover {
+            id: Some(id),
+            ..handover
+        })
+    }
+
+    async fn find_handover(&self, claim_id: &str) -> Result<Handover> {
+        let claim_id = ObjectId::parse_str(claim_id).map_err(|_| Error::InvalidId)?;
+        self.handover_collection
+            .find_one(doc! { "claim_id": claim_id }, None)
+            .await?
+            .ok_or(Error::HandoverNotFound)
+    }
+
+    /// The handover for an accepted claim. Only the owner gets the code.
+    pub async fn get_handover(&self, claim_id: &str, actor: &str) -> Result<Handover> {
+        let mut handover = self.find_handover(claim_id).await?;
+        if handover.owner != actor && handover.finder != actor {
+            return Err(Error::Forbidden);
+        }
+        if handover.owner != actor {
+            handover.code = None;
+        }
+        Ok(handover)
+    }
+
+    /// Called by the finder with the code the owner showed them. Marks the
+    /// post returned and consumes the code.
+    pub async fn confirm_handover(&self, claim_id: &str, finder: &str, code: &str) -> Result<Handover> {
+        let handover = self.find_handover(claim_id).await?;
+        let id = handover.id.ok_or(Error::HandoverNotFound)?;
+        if handover.finder != finder {
+            return Err(Error::Forbidden);
+        }
+        if handover.status != HandoverStatus::Pending {
+            return Err(Error::InvalidStatusTransition);
+        }
+        let expected = handover.code.as_deref().ok_or(Error::InvalidHandoverCode)?;
+        if !handover_code_matches(code, expected) {
+            return Err(Error::InvalidHandoverCode);
+        }
+
+        // Only the post's currently accepted claim can complete it; a claim
+        // released when the post was reopened keeps its old code.
+        let accepted = doc! {
+            "_id": handover.claim_id,
+            "post_id": handover.post_id,
+            "status": ClaimStatus::Accepted.as_str(),
+        };
+        if self.claim_collection.find_one(accepted, None).await?.is_none() {
+            return Err(Error::InvalidStatusTransition);
+        }
+        let post = self.find_live_post(&handover.post_id.to_hex()).await?;
+        if post.status != PostStatus::Claimed {
+            return Err(Error::InvalidStatusTransition);
+        }
+        self.set_post_status(&post, finder, PostStatus::Returned).await?;
+
+        let completed_at = Utc::now();
+        self.handover_collection
+            .update_one(
+                doc! { "_id": id, "status": HandoverStatus::Pending.as_str() },
+                doc! {
+                    "$set": {
+                        "status": HandoverStatus::Completed.as_str(),
+                        "category": post.category.as_str(),
+                        "completed_at": timestamp::to_bson(&completed_at),
+                    },
+                    "$unset": { "code": "" },
+                },
+                None,
+            )
             .await?;
 
-        if result.matched_count == 0 {
-            return Err(Error::PostNotFound);
+        Ok(Handover {
+            code: None,
+            status: HandoverStatus::Completed,
+            category: Some(post.category),
+            completed_at: Some(completed_at),
+            ..handover
+        })
+    }
+
+    /// Completed handovers the user took part in, as owner or finder.
+    pub async fn get_handover_history(&self, username: &str) -> Result<Vec<Handover>> {
+        let filter = doc! {
+            "status": HandoverStatus::Completed.as_str(),
+            "$or": [{ "owner": username }, { "finder": username }],
+        };
+        let options = FindOptions::builder()
+            .sort(doc! { "completed_at": -1 })
+            .projection(doc! { "code": 0 })
+            .build();
+        let cursor = self.handover_collection.find(filter, options).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    pub async fn return_stats(&self) -> Result<ReturnStats> {
+        let since = Utc::now() - chrono::Duration::days(30);
+        let pipeline = vec![
+            doc! { "$match": { "status": HandoverStatus::Completed.as_str() } },
+            doc! {
+                "$facet": {
+                    "total": [{ "$count": "count" }],
+                    "recent": [
+                        { "$match": { "completed_at": { "$gte": timestamp::to_bson(&since) } } },
+                        { "$count": "count" },
+                    ],
+                    // The post id carries its creation time.
+                    "duration": [{
+                        "$group": {
+                            "_id": null,
+                            "avg_ms": {
+                                "$avg": { "$subtract": ["$completed_at", { "$toDate": "$post_id" }] }
+                            },
+                        }
+                    }],
+                    "by_category": [
+                        { "$group": { "_id": "$category", "count": { "$sum": 1 } } },
+                        { "$sort": { "count": -1, "_id": 1 } },
+                    ],
+                }
+            },
+        ];
+
+        let mut cursor = self.handover_collection.aggregate(pipeline, None).await?;
+        let facets = cursor.try_next().await?.unwrap_or_default();
+
+        let first = |name: &str| -> Option<Document> {
+            facets
+                .get_array(name)
+                .ok()
+                .and_then(|values| values.first())
+                .and_then(|value| value.as_document())
+                .cloned()
+        };
+        let count = |name: &str| {
+            first(name)
+                .and_then(|doc| doc.get("count").cloned())
+                .and_then(|count| match count {
+                    Bson::Int32(n) => Some(n as i64),
+                    Bson::Int64(n) => Some(n),
+                    _ => None,
+                })
+                .unwrap_or(0)
+        };
+        let avg_hours_to_return = first("duration")
+            .and_then(|doc| doc.get_f64("avg_ms").ok())
+            .map(|ms| ms / 3_600_000.0);
+        let by_category = facets
+            .get_array("by_category")
+            .map(|values| {
+                values
+                    .iter()
+                    .filter_map(|value| value.as_document())
+                    .map(|doc| CategoryCount {
+                        category: doc
+                            .get_str("_id")
+                            .ok()
+                            .and_then(|category| {
+                                Category::ALL.iter().copied().find(|c| c.as_str() == category)
+                            })
+                            .unwrap_or_default(),
+                        count: doc.get_i32("count").map(i64::from).unwrap_or(0),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Ok(ReturnStats {
+            total_returns: count("total"),
+            returns_last_30_days: count("recent"),
+            avg_hours_to_return,
+            by_category,
+        })
+    }
+
+    /// Validates an uploaded image and puts it in storage. The format is
+    /// taken from the file's magic bytes; a declared content type only has
+    /// to agree that it is an image.
+    pub async fn store_image(
+        &self,
+        owner: &str,
+        bytes: Vec<u8>,
+        declared_type: Option<&str>,
+    ) -> Result<Upload> {
+        if bytes.len() > config().storage.max_upload_bytes {
+            return Err(Error::PayloadTooLarge);
+        }
+        if bytes.is_empty() {
+            return Err(Error::InvalidInput("The file is empty".to_string()));
         }
+        if declared_type.is_some_and(|content_type| !content_type.starts_with("image/")) {
+            return Err(Error::UnsupportedMediaType);
+        }
+        ImageFormat::sniff(&bytes).ok_or(Error::UnsupportedMediaType)?;
 
-        Ok(())
+        // Strips metadata and fixes orientation; the original bytes are
+        // never stored.
+        let processed = tokio::task::spawn_blocking(move || imaging::process(&bytes))
+            .await
+            .map_err(|_| Error::Custom)??;
+
+        let id = ObjectId::new();
+        let key = |size: ImageSize| {
+            format!("images/{}/{}.{}", id.to_hex(), size.as_str(), imaging::EXTENSION)
+        };
+        let upload = Upload {
+            id,
+            owner: owner.to_string(),
+            key: key(ImageSize::Full),
+            content_type: imaging::CONTENT_TYPE.to_string(),
+            size: processed.full.len() as i64,
+            thumbnails: Some(ThumbnailKeys {
+                detail: key(ImageSize::Detail),
+                feed: key(ImageSize::Feed),
+            }),
+            width: Some(processed.width),
+            height: Some(processed.height),
+            image_hash: Some(imaging::hash_to_hex(processed.hash)),
+            created_at: Utc::now(),
+        };
+
+        let files = [
+            (ImageSize::Full, processed.full),
+            (ImageSize::Detail, processed.detail),
+            (ImageSize::Feed, processed.feed),
+        ];
+        let mut stored = Vec::new();
+        let mut result = Ok(());
+        for (size, bytes) in files {
+            let key = upload.key_for(size).to_string();
+            result = self.storage.put(&key, bytes, imaging::CONTENT_TYPE).await;
+            if result.is_err() {
+                break;
+            }
+            stored.push(key);
+        }
+        if result.is_ok() {
+            result = self
+                .upload_collection
+                .insert_one(&upload, None)
+                .await
+                .map(|_| ())
+                .map_err(Error::from);
+        }
+        if let Err(e) = result {
+            // Don't leave orphaned files behind.
+            for key in stored {
+                if let Err(e) = self.storage.delete(&key).await {
+                    eprintln!("Failed to remove orphaned upload {}: {:?}", key, e);
+                }
+            }
+            return Err(e);
+        }
+
+        Ok(upload)
     }
 
-    pub async fn getallcomments(&self, post_id: String) -> Result<Vec<Comment>> {
-        let object_id = match mongodb::bson::oid::ObjectId::parse_str(&post_id) {
-            Ok(oid) => oid,
-            Err(_) => return Err(Error::PostNotFound),
+    /// Live posts by `user` whose photo is a near duplicate of `image_hash`.
+    pub async fn find_duplicate_posts(&self, user: &str, image_hash: &str) -> Result<Vec<ObjectId>> {
+        let filter = doc! {
+            "user": user,
+            "deleted_at": null,
+            "image_hash": { "$ne": null },
         };
-        let post = self
+        let options = FindOptions::builder()
+            .projection(doc! { "image_hash": 1 })
+            .build();
+        let cursor = self
             .post_collection
-            .find_one(doc! { "_id": object_id }, None)
-            .await?
-            .ok_or(Error::PostNotFound)?;
-        Ok(post.comments)
+            .clone_with_type::<Document>()
+            .find(filter, options)
+            .await?;
+        let posts: Vec<Document> = cursor.try_collect().await?;
+
+        Ok(posts
+            .iter()
+            .filter(|post| {
+                post.get_str("image_hash")
+                    .is_ok_and(|hash| imaging::is_near_duplicate(hash, image_hash))
+            })
+            .filter_map(|post| post.get_object_id("_id").ok())
+            .collect())
     }
 
-    pub async fn create_or_get_chat(&self, user1: String, user2: String) -> Result<ChatRoom> {
-        let user1_exists = self
-            .user_collection
-            .find_one(doc! { "username": &user1 }, None)
-            .await?;
-        if user1_exists.is_none() {
-            return Err(Error::UserNotFound);
+    /// An upload the user may attach to their post.
+    pub async fn find_upload(&self, image_id: &str, owner: &str) -> Result<Upload> {
+        let image_id = ObjectId::parse_str(image_id).map_err(|_| Error::InvalidId)?;
+        let upload = self
+            .upload_collection
+            .find_one(doc! { "_id": image_id }, None)
+            .await?
+            .ok_or(Error::ImageNotFound)?;
+        if upload.owner != owner {
+            return Err(Error::Forbidden);
         }
+        Ok(upload)
+    }
 
-        let user2_exists = self
+    pub async fn get_image(&self, image_id: &str, size: ImageSize) -> Result<StoredObject> {
+        let image_id = ObjectId::parse_str(image_id).map_err(|_| Error::InvalidId)?;
+        let upload = self
+            .upload_collection
+            .find_one(doc! { "_id": image_id }, None)
+            .await?
+            .ok_or(Error::ImageNotFound)?;
+
+        self.storage
+            .get(upload.key_for(size))
+            .await?
+            .ok_or(Error::ImageNotFound)
+    }
+
+    pub async fn set_profile_picture(&self, username: &str, url: &str) -> Result<()> {
+        let result = self
             .user_collection
-            .find_one(doc! { "username": &user2 }, None)
+            .update_one(
+                doc! { "username": username },
+                doc! { "$set": { "profile_picture": url } },
+                None,
+            )
             .await?;
-        if user2_exists.is_none() {
+
+        if result.matched_count == 0 {
             return Err(Error::UserNotFound);
         }
+        Ok(())
+    }
 
-        let filter = doc! {
-            "$or": [
-                { "user1": &user1, "user2": &user2 },
-                { "user1": &user2, "user2": &user1 }
-            ]
+    /// Directory entries whose name, aliases or building start with every
+    /// word of `query`; all of them when there is no query.
+    pub async fn list_locations(&self, query: Option<&str>, limit: i64) -> Result<Vec<Location>> {
+        let words = location_terms(query.unwrap_or_default(), &[], None);
+        let filter = if words.is_empty() {
+            doc! {}
+        } else {
+            let prefixes: Vec<Bson> = words
+                .iter()
+                .map(|word| {
+                    Bson::RegularExpression(mongodb::bson::Regex {
+                        // Terms are plain alphanumerics, nothing to escape.
+                        pattern: format!("^{}", word),
+                        options: String::new(),
+                    })
+                })
+                .collect();
+            doc! { "search_terms": { "$all": prefixes } }
         };
-        if let Some(chat) = self.chat_room_collection.find_one(filter, None).await? {
-            println!("Found existing chat with ID: {:?}", chat.id);
-       
-            return Ok(chat);
+        let options = FindOptions::builder()
+            .sort(doc! { "name": 1 })
+            .limit(limit.clamp(1, MAX_LOCATION_LIMIT))
+            .build();
+        let cursor = self.location_collection.find(filter, options).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    pub async fn find_location(&self, location_id: &str) -> Result<Location> {
+        let location_id = ObjectId::parse_str(location_id).map_err(|_| Error::InvalidId)?;
+        self.location_collection
+            .find_one(doc! { "_id": location_id }, None)
+            .await?
+            .ok_or(Error::LocationNotFound)
+    }
+
+    /// Validates admin input into a directory entry.
+    async fn build_location(&self, input: LocationInput) -> Result<Location> {
+        let name = input.name.trim().to_string();
+        if name.is_empty() || name.chars().count() > MAX_LOCATION_NAME_LEN {
+            return Err(Error::InvalidInput(format!(
+                "Location names must be between 1 and {} characters",
+                MAX_LOCATION_NAME_LEN
+            )));
         }
-        let now = chrono::Utc::now().to_rfc3339();
-        let chat_room = ChatRoom {
-            id: None,
-            user1,
-            user2,
-            created_at: now,
+        let mut aliases: Vec<String> = input
+            .aliases
+            .into_iter()
+            .map(|alias| alias.trim().to_string())
+            .filter(|alias| !alias.is_empty())
+            .collect();
+        // Drop repeats anywhere in the list, keeping the admin's order.
+        let mut seen = std::collections::HashSet::new();
+        aliases.retain(|alias| seen.insert(alias.clone()));
+        if aliases.len() > MAX_LOCATION_ALIASES
+            || aliases.iter().any(|alias| alias.chars().count() > MAX_LOCATION_NAME_LEN)
+        {
+            return Err(Error::InvalidInput(format!(
+                "At most {} aliases of up to {} characters are allowed",
+                MAX_LOCATION_ALIASES, MAX_LOCATION_NAME_LEN
+            )));
+        }
+        let coordinates = input.coordinates.map(Coordinates::validated).transpose()?;
+
+        let parent = match (input.kind, input.parent_id) {
+            (LocationKind::Room, Some(parent_id)) => {
+                let parent = self.find_location(&parent_id).await?;
+                if parent.kind != LocationKind::Building {
+                    return Err(Error::InvalidInput("Rooms must belong to a building".to_string()));
+                }
+                Some(parent)
+            }
+            (LocationKind::Room, None) => {
+                return Err(Error::InvalidInput("Rooms need a parent building".to_string()));
+            }
+            (LocationKind::Building, Some(_)) => {
+                return Err(Error::InvalidInput("Buildings cannot have a parent".to_string()));
+            }
+            (LocationKind::Building, None) => None,
         };
-        let insert_result = self
-            .chat_room_collection
-            .insert_one(&chat_room, None)
-            .await?;
+        let building = parent.as_ref().map(|parent| parent.name.clone());
+
+        Ok(Location {
+            id: None,
+            search_terms: location_terms(&name, &aliases, building.as_deref()),
+            name,
+            kind: input.kind,
+            parent_id: parent.and_then(|parent| parent.id),
+            building,
+            aliases,
+            coordinates,
+        })
+    }
+
+    pub async fn create_location(&self, input: LocationInput) -> Result<Location> {
+        let location = self.build_location(input).await?;
+        let insert_result = self.location_collection.insert_one(&location, None).await?;
         let id = insert_result
             .inserted_id
             .as_object_id()
-            .ok_or(Error::DatabaseError(String::from(
-                "Failed to get inserted ID",
-            )))?;
+            .ok_or(Error::DatabaseError("Failed to get inserted ID".into()))?;
 
-        Ok(ChatRoom {
+        Ok(Location {
             id: Some(id),
-            ..chat_room
+            ..location
         })
     }
-    pub async fn send_message(
+
+    /// Replaces a directory entry. Renaming a building renames it in the
+    /// autocomplete entries of its rooms as well.
+    pub async fn update_location(&self, location_id: &str, input: LocationInput) -> Result<Location> {
+        let existing = self.find_location(location_id).await?;
+        let id = existing.id.ok_or(Error::LocationNotFound)?;
+        if existing.kind == LocationKind::Building && input.kind == LocationKind::Room {
+            let rooms = self
+                .location_collection
+                .count_documents(doc! { "parent_id": id }, None)
+                .await?;
+            if rooms > 0 {
+                return Err(Error::InvalidInput(
+                    "A building with rooms cannot become a room".to_string(),
+                ));
+            }
+        }
+        let location = Location {
+            id: Some(id),
+            ..self.build_location(input).await?
+        };
+        if location.parent_id == Some(id) {
+            return Err(Error::InvalidInput("A location cannot contain itself".to_string()));
+        }
+        self.location_collection
+            .replace_one(doc! { "_id": id }, &location, None)
+            .await?;
+
+        if location.coordinates != existing.coordinates {
+            self.move_derived_posts(&location).await?;
+        }
+
+        if location.kind == LocationKind::Building && location.name != existing.name {
+            let cursor = self
+                .location_collection
+                .find(doc! { "parent_id": id }, None)
+                .await?;
+            let rooms: Vec<Location> = cursor.try_collect().await?;
+            for room in rooms {
+                let terms = location_terms(&room.name, &room.aliases, Some(&location.name));
+                self.location_collection
+                    .update_one(
+                        doc! { "_id": room.id },
+                        doc! { "$set": { "building": &location.name, "search_terms": terms } },
+                        None,
+                    )
+                    .await?;
+            }
+        }
+
+        Ok(location)
+    }
+
+    /// Removes a directory entry nothing points at any more.
+    pub async fn delete_location(&self, location_id: &str) -> Result<()> {
+        let location = self.find_location(location_id).await?;
+        let id = location.id.ok_or(Error::LocationNotFound)?;
+
+        let rooms = self
+            .location_collection
+            .count_documents(doc! { "parent_id": id }, None)
+            .await?;
+        let posts = self
+            .post_collection
+            .count_documents(doc! { "location_id": id }, None)
+            .await?;
+        if rooms > 0 || posts > 0 {
+            return Err(Error::InvalidInput(
+                "The location still has rooms or posts".to_string(),
+            ));
+        }
+
+        self.location_collection
+            .delete_one(doc! { "_id": id }, None)
+            .await?;
+        Ok(())
+    }
+
+    /// Coordinates of a location; rooms without their own fall back to
+    /// their building's.
+    pub async fn location_coordinates(&self, location: &Location) -> Result<Option<Coordinates>> {
+        if location.coordinates.is_some() {
+            return Ok(location.coordinates);
+        }
+        let Some(parent_id) = location.parent_id else {
+            return Ok(None);
+        };
+        let parent = self
+            .location_collection
+            .find_one(doc! { "_id": parent_id }, None)
+            .await?;
+        Ok(parent.and_then(|parent| parent.coordinates))
+    }
+
+    /// The point stored on a post: the author's coordinates when given,
+    /// otherwise the location's. The flag tells whether it was derived.
+    pub async fn post_geo(
         &self,
-        chat_id: &str,
-        sender: String,
-        message: String,
-    ) -> Result<ChatMessage> {
-        let chat_obj_id = ObjectId::parse_str(chat_id).map_err(|_| Error::InvalidToken)?;
-        let chat = self
-            .chat_room_collection
-            .find_one(doc! { "_id": &chat_obj_id }, None)
-            .await?
-            .ok_or(Error::ChatNotFound)?;
+        coordinates: Option<Coordinates>,
+        location: Option<&Location>,
+    ) -> Result<(Option<GeoPoint>, bool)> {
+        if let Some(coordinates) = coordinates {
+            return Ok((Some(coordinates.validated()?.into()), false));
+        }
+        let Some(location) = location else {
+            return Ok((None, false));
+        };
+        let derived = self.location_coordinates(location).await?;
+        Ok((derived.map(GeoPoint::from), derived.is_some()))
+    }
 
-        if chat.user1 != sender && chat.user2 != sender {
-            return Err(Error::Unauthorized);
+    /// Re-derives the point of posts placed at `location`, and at rooms of
+    /// it that borrow its coordinates, after the location moved.
+    async fn move_derived_posts(&self, location: &Location) -> Result<()> {
+        let id = location.id.ok_or(Error::LocationNotFound)?;
+        let mut ids = vec![id];
+        if location.kind == LocationKind::Building {
+            let cursor = self
+                .location_collection
+                .find(doc! { "parent_id": id, "coordinates": null }, None)
+                .await?;
+            let rooms: Vec<Location> = cursor.try_collect().await?;
+            ids.extend(rooms.into_iter().filter_map(|room| room.id));
         }
 
-        let now = chrono::Utc::now().to_rfc3339();
-        let chat_message = ChatMessage {
-            id: None,
-            char_id: chat_id.to_string(),
-            sender,
-            message,
-            send_at: now,
-            is_read: false,
+        let filter = doc! {
+            "location_id": { "$in": ids },
+            "$or": [{ "geo_derived": true }, { "geo": null }],
         };
+        let update = match self.location_coordinates(location).await? {
+            Some(coordinates) => doc! {
+                "$set": {
+                    "geo": to_bson(&GeoPoint::from(coordinates)).map_err(|_| Error::Custom)?,
+                    "geo_derived": true,
+                }
+            },
+            None => doc! { "$unset": { "geo": "", "geo_derived": "" } },
+        };
+        self.post_collection.update_many(filter, update, None).await?;
+        Ok(())
+    }
 
-        let insert_result = self.chat_message_collection.insert_one(&chat_message, None).await?;
-        let id = insert_result
-            .inserted_id
-            .as_object_id()
-            .ok_or(Error::DatabaseError("Failed to get inserted ID".into()))?;
+    /// Posts within `radius_m` meters of `center`, nearest first.
+    pub async fn posts_near(
+        &self,
+        center: Coordinates,
+        radius_m: f64,
+        filter: &PostFilter,
+        limit: i64,
+    ) -> Result<Vec<NearbyPost>> {
+        let center = GeoPoint::from(center.validated()?);
+        // $geoNear has to be the first stage and does the sorting itself.
+        let mut pipeline = vec![
+            doc! {
+                "$geoNear": {
+                    "near": to_bson(&center).map_err(|_| Error::Custom)?,
+                    "distanceField": "distance_m",
+                    "maxDistance": radius_m,
+                    "query": filter.to_document()?,
+                    "spherical": true,
+                }
+            },
+            doc! { "$limit": limit },
+        ];
+        pipeline.extend(self.author_lookup());
 
-        Ok(ChatMessage {
-            id: Some(id),
-            ..chat_message
+        let mut cursor = self.post_collection.aggregate(pipeline, None).await?;
+        let mut results = Vec::new();
+        while let Some(mut document) = cursor.try_next().await? {
+            let distance_m = document.remove("distance_m").and_then(|d| d.as_f64()).unwrap_or_default();
+            if let (post, Some(author)) = split_author(document)? {
+                results.push(NearbyPost {
+                    post: PostWithUser::new(post, author),
+                    distance_m,
+                });
+            }
+        }
+
+        Ok(results)
+    }
+
+    /// Converts the dates older versions stored as strings (`posts.date`,
+    /// `chat_rooms.created_at`, `chat.send_at`) to BSON datetimes, so they
+    /// sort and range-query properly. Only touches documents still holding
+    /// strings. Unparseable values fall back to the document's creation
+    /// time. Applied as migration 1.
+    pub async fn migrate_string_dates(&self) -> Result<u64> {
+        let targets = [
+            (self.post_collection.clone_with_type::<Document>(), "date"),
+            (self.chat_room_collection.clone_with_type::<Document>(), "created_at"),
+            (self.chat_message_collection.clone_with_type::<Document>(), "send_at"),
+        ];
+
+        let mut migrated = 0;
+        for (collection, field) in targets {
+            let value = format!("${}", field);
+            let update = vec![doc! {
+                "$set": {
+                    field: {
+                        "$dateFromString": {
+                            "dateString": &value,
+                            "onError": { "$toDate": "$_id" },
+                        }
+                    }
+                }
+            }];
+            let result = collection
+                .update_many(doc! { field: { "$type": "string" } }, update, None)
+                .await?;
+            migrated += result.modified_count;
+        }
+        Ok(migrated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn post(id: ObjectId, date: &str) -> MissingPost {
+        from_document(doc! {
+            "_id": id,
+            "description": "Blue bottle",
+            "date": timestamp::to_bson(&timestamp::parse(date).unwrap()),
+            "place": "Library",
+            "image_link": "",
+            "user": "alice",
+            "comments": [],
         })
+        .unwrap()
     }
-    pub async fn get_chat_messages(&self, chat_id: &str) -> Result<Vec<ChatMessage>> {
-        println!("Getting messages for chat ID: {}", chat_id);
-        
-        // Find messages for this chat
-        let filter = doc! { "char_id": chat_id }; // Make sure field name matches your struct
-        
-        let options = mongodb::options::FindOptions::builder()
-            .sort(doc! { "send_at": 1 }) // Sort by time, oldest first
-            .build();
-            
-        let mut cursor = self.chat_message_collection.find(filter, options).await?;
-        
-        let mut messages = Vec::new();
-        while let Some(message) = cursor.try_next().await? {
-            messages.push(message);
-        }
-        
-        println!("Found {} messages", messages.len());
-        
-        Ok(messages)
+
+    fn page(sort: PostSort, cursor: Option<String>) -> PageRequest {
+        PageRequest { sort, limit: None, cursor }
     }
-    pub async fn get_chat_rooms(&self, username: &str) -> Result<Vec<ChatRoom>> {
-        let filter = doc! {
-            "$or": [
-                { "user1": username },
-                { "user2": username }
-            ]
+
+    #[test]
+    fn page_cursor_round_trips_to_an_id_bound() {
+        let id = ObjectId::new();
+        let newest = page(PostSort::Newest, None);
+        let cursor = newest.encode_cursor(&post(id, "2024-05-01")).unwrap();
+
+        let filter = page(PostSort::Newest, Some(cursor.clone())).cursor_filter().unwrap();
+        assert_eq!(filter, Some(doc! { "_id": { "$lt": id } }));
+        let filter = page(PostSort::Oldest, Some(cursor)).cursor_filter().unwrap();
+        assert_eq!(filter, Some(doc! { "_id": { "$gt": id } }));
+    }
+
+    #[test]
+    fn date_lost_cursor_keeps_the_date_as_a_bson_datetime() {
+        let id = ObjectId::new();
+        let sort = page(PostSort::DateLost, None);
+        let cursor = sort.encode_cursor(&post(id, "2024-05-01T10:30:00Z")).unwrap();
+
+        let date = Bson::DateTime(DateTime::parse_rfc3339_str("2024-05-01T10:30:00Z").unwrap());
+        let filter = page(PostSort::DateLost, Some(cursor)).cursor_filter().unwrap();
+        assert_eq!(
+            filter,
+            Some(doc! {
+                "$or": [
+                    { "date": { "$lt": &date } },
+                    { "date": &date, "_id": { "$lt": id } },
+                ]
+            })
+        );
+    }
+
+    #[test]
+    fn malformed_page_cursors_are_rejected() {
+        assert!(page(PostSort::Newest, None).cursor_filter().unwrap().is_none());
+        for cursor in ["not base64!", "AAAA", &URL_SAFE_NO_PAD.encode(b"junk")] {
+            let result = page(PostSort::Newest, Some(cursor.to_string())).cursor_filter();
+            assert!(matches!(result, Err(Error::InvalidInput(_))), "{cursor}");
+        }
+        // A date-sorted cursor needs its date.
+        let id_only = page(PostSort::Newest, None).encode_cursor(&post(ObjectId::new(), "2024-05-01"));
+        let result = page(PostSort::DateLost, id_only).cursor_filter();
+        assert!(matches!(result, Err(Error::InvalidInput(_))));
+    }
+
+    fn attributes(color: &str, brand: &str, serial: &str) -> ItemAttributes {
+        let some = |value: &str| Some(value.to_string());
+        ItemAttributes { color: some(color), brand: some(brand), serial_last4: some(serial) }
+    }
+
+    #[test]
+    fn attributes_are_trimmed_and_normalized() {
+        let cleaned = attributes("  Navy Blue ", " Apple ", "a1b2").validated().unwrap();
+        assert_eq!(cleaned, attributes("navy blue", "Apple", "A1B2"));
+
+        let blank = attributes(" ", "", "  ").validated().unwrap();
+        assert_eq!(blank, ItemAttributes::default());
+    }
+
+    #[test]
+    fn invalid_attributes_are_rejected() {
+        for serial in ["123", "12345", "12-4"] {
+            assert!(matches!(
+                attributes("red", "", serial).validated(),
+                Err(Error::InvalidInput(_))
+            ));
+        }
+        let long = "x".repeat(MAX_ATTRIBUTE_LEN + 1);
+        assert!(matches!(attributes(&long, "", "").validated(), Err(Error::InvalidInput(_))));
+    }
+
+    #[test]
+    fn legacy_posts_without_a_category_filter_as_other() {
+        assert_eq!(Category::Other.filter_value(), Bson::from(doc! { "$in": ["other", null] }));
+        assert_eq!(Category::Keys.filter_value(), Bson::from("keys"));
+    }
+
+    fn utc(value: &str) -> chrono::DateTime<Utc> {
+        chrono::DateTime::parse_from_rfc3339(value).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn timestamps_parse_the_iso_forms_clients_send() {
+        assert_eq!(timestamp::parse("2024-05-01T10:30:00.250Z").unwrap(), utc("2024-05-01T10:30:00.250Z"));
+        assert_eq!(timestamp::parse("2024-05-01T12:30:00+02:00").unwrap(), utc("2024-05-01T10:30:00Z"));
+        // `datetime-local` inputs carry no offset and are taken as UTC.
+        assert_eq!(timestamp::parse("2024-05-01T10:30").unwrap(), utc("2024-05-01T10:30:00Z"));
+        assert_eq!(timestamp::parse("2024-05-01T10:30:15.5").unwrap(), utc("2024-05-01T10:30:15.500Z"));
+        assert_eq!(timestamp::parse(" 2024-05-01 ").unwrap(), utc("2024-05-01T00:00:00Z"));
+
+        for invalid in ["", "yesterday", "2024-13-01", "01/05/2024"] {
+            assert!(timestamp::parse(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn timestamps_are_stored_as_datetimes_and_read_from_either_form() {
+        let at = utc("2024-05-01T10:30:00Z");
+        let room = ChatRoom {
+            id: None,
+            user1: "alice".to_string(),
+            user2: "bob".to_string(),
+            created_at: at,
+            post_id: None,
         };
+        // The driver writes documents through the raw (binary) serializer.
+        let bytes = mongodb::bson::to_vec(&room).unwrap();
+        let stored = Document::from_reader(bytes.as_slice()).unwrap();
+        assert_eq!(stored.get("created_at"), Some(&Bson::DateTime(timestamp::to_bson(&at))));
+        assert_eq!(from_document::<ChatRoom>(stored).unwrap().created_at, at);
 
-        let mut cursor = self.chat_room_collection.find(filter, None).await?;
-        let mut chat_rooms = Vec::new();
+        let legacy = doc! { "user1": "alice", "user2": "bob", "created_at": "2024-05-01T10:30:00Z" };
+        assert_eq!(from_document::<ChatRoom>(legacy).unwrap().created_at, at);
 
-        while let Some(chat_room) = cursor.try_next().await? {
-            chat_rooms.push(chat_room);
+        // Responses keep sending strings.
+        let json = serde_json::to_value(&room).unwrap();
+        assert_eq!(json["created_at"], "2024-05-01T10:30:00.000Z");
+    }
+
+    fn message(at: &str) -> ChatMessage {
+        ChatMessage {
+            id: Some(ObjectId::new()),
+            char_id: ObjectId::new().to_hex(),
+            sender: "alice".to_string(),
+            message: "hi".to_string(),
+            send_at: utc(at),
+            is_read: false,
         }
+    }
 
-        Ok(chat_rooms)
+    fn history(since: Option<&str>, before: Option<&str>, cursor: Option<String>) -> MessageQuery {
+        MessageQuery { since: since.map(utc), before: before.map(utc), limit: None, cursor }
+    }
+
+    #[test]
+    fn message_cursor_round_trips_with_its_direction() {
+        let last = message("2024-05-01T10:30:00Z");
+        let at = timestamp::to_bson(&last.send_at);
+        let id = last.id.unwrap();
+
+        for forward in [true, false] {
+            let cursor = encode_message_cursor(&last, forward).unwrap();
+            let (position, decoded_forward) = history(None, None, Some(cursor)).position().unwrap();
+            assert_eq!(decoded_forward, forward);
+            let op = if forward { "$gt" } else { "$lt" };
+            assert_eq!(
+                position.unwrap().filter(forward),
+                doc! {
+                    "$or": [
+                        { "send_at": { op: at } },
+                        { "send_at": at, "_id": { op: id } },
+                    ]
+                }
+            );
+        }
     }
 
+    #[test]
+    fn since_reads_forward_and_before_reads_back() {
+        let since = "2024-05-01T10:00:00Z";
+        let (position, forward) = history(Some(since), None, None).position().unwrap();
+        assert!(forward);
+        assert_eq!(
+            position.unwrap().filter(forward),
+            doc! { "send_at": { "$gt": timestamp::to_bson(&utc(since)) } }
+        );
+
+        let (position, forward) = history(None, Some(since), None).position().unwrap();
+        assert!(!forward);
+        assert_eq!(
+            position.unwrap().filter(forward),
+            doc! { "send_at": { "$lt": timestamp::to_bson(&utc(since)) } }
+        );
+
+        // No parameters: the latest messages, read back from now.
+        let (position, forward) = history(None, None, None).position().unwrap();
+        assert!(position.is_none() && !forward);
+    }
+
+    #[test]
+    fn conflicting_or_malformed_history_parameters_are_rejected() {
+        let cursor = encode_message_cursor(&message("2024-05-01T10:00:00Z"), true);
+        let at = Some("2024-05-01T10:00:00Z");
+        for query in [
+            history(at, at, None),
+            history(at, None, cursor.clone()),
+            history(None, at, cursor),
+            history(None, None, Some("garbage".to_string())),
+        ] {
+            assert!(matches!(query.position(), Err(Error::InvalidInput(_))));
+        }
+    }
+
+    #[test]
+    fn message_page_size_is_clamped() {
+        let mut query = MessageQuery::default();
+        assert_eq!(query.limit(), DEFAULT_MESSAGE_PAGE);
+        query.limit = Some(0);
+        assert_eq!(query.limit(), 1);
+        query.limit = Some(10_000);
+        assert_eq!(query.limit(), MAX_MESSAGE_PAGE);
+    }
 }
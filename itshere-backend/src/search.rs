@@ -0,0 +1,177 @@
+//! Presentation of post search results. Mongo's text index finds and ranks
+//! the matches; this module marks the query terms in the returned text.
+
+use crate::model::PostWithUser;
+use serde::Serialize;
+
+const MARK_START: &str = "<mark>";
+const MARK_END: &str = "</mark>";
+
+#[derive(Debug, Serialize)]
+pub struct Highlight {
+    pub field: &'static str,
+    /// The field's text, HTML-escaped, with matched words wrapped in
+    /// `<mark>` tags.
+    pub text: String,
+    /// Byte ranges `[start, end)` of the matched words in the raw field,
+    /// for clients that do their own markup.
+    pub matches: Vec<[usize; 2]>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchResult {
+    #[serde(flatten)]
+    pub post: PostWithUser,
+    pub score: f64,
+    pub highlights: Vec<Highlight>,
+}
+
+impl SearchResult {
+    pub fn new(post: PostWithUser, score: f64, terms: &[String]) -> Self {
+        let highlights = [("description", &post.description), ("place", &post.place)]
+            .into_iter()
+            .filter_map(|(field, text)| {
+                let matches = match_ranges(text, terms);
+                (!matches.is_empty()).then(|| Highlight {
+                    field,
+                    text: highlight(text, &matches),
+                    matches,
+                })
+            })
+            .collect();
+
+        Self {
+            post,
+            score,
+            highlights,
+        }
+    }
+}
+
+/// Lowercased, roughly stemmed terms of a `$text` query. Negated terms
+/// (`-word`) are left out since they never appear in a match.
+pub fn query_terms(query: &str) -> Vec<String> {
+    query
+        .split_whitespace()
+        .filter(|token| !token.starts_with('-'))
+        .flat_map(|token| token.split(|c: char| !c.is_alphanumeric()))
+        .filter(|word| !word.is_empty())
+        .map(|word| stem(&word.to_lowercase()).to_string())
+        .collect()
+}
+
+/// Strips common English suffixes so "bottles" also marks "bottle", close
+/// enough to the stemming Mongo applies when matching.
+pub(crate) fn stem(word: &str) -> &str {
+    for suffix in ["ing", "ed", "es", "s"] {
+        if let Some(stem) = word.strip_suffix(suffix)
+            && stem.chars().count() >= 3
+        {
+            return stem;
+        }
+    }
+    word
+}
+
+/// Byte ranges of the words in `text` that start with one of `terms`.
+pub fn match_ranges(text: &str, terms: &[String]) -> Vec<[usize; 2]> {
+    let mut ranges = Vec::new();
+    let mut offset = 0;
+
+    while offset < text.len() {
+        let rest = &text[offset..];
+        let word_start = offset + rest.find(char::is_alphanumeric).unwrap_or(rest.len());
+        let rest = &text[word_start..];
+        let word_end = word_start
+            + rest
+                .find(|c: char| !c.is_alphanumeric())
+                .unwrap_or(rest.len());
+
+        let lower = text[word_start..word_end].to_lowercase();
+        if !lower.is_empty() && terms.iter().any(|term| lower.starts_with(term.as_str())) {
+            ranges.push([word_start, word_end]);
+        }
+        offset = word_end;
+    }
+
+    ranges
+}
+
+/// HTML-escapes `text` and wraps the given ranges in mark tags. User text
+/// must never reach a client as markup.
+pub fn highlight(text: &str, ranges: &[[usize; 2]]) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut offset = 0;
+    for &[start, end] in ranges {
+        escape_into(&mut output, &text[offset..start]);
+        output.push_str(MARK_START);
+        escape_into(&mut output, &text[start..end]);
+        output.push_str(MARK_END);
+        offset = end;
+    }
+    escape_into(&mut output, &text[offset..]);
+    output
+}
+
+fn escape_into(output: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '&' => output.push_str("&amp;"),
+            '<' => output.push_str("&lt;"),
+            '>' => output.push_str("&gt;"),
+            '"' => output.push_str("&quot;"),
+            '\'' => output.push_str("&#39;"),
+            c => output.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn terms(query: &str) -> Vec<String> {
+        query_terms(query)
+    }
+
+    #[test]
+    fn stem_strips_common_suffixes() {
+        assert_eq!(stem("bottles"), "bottl");
+        assert_eq!(stem("keys"), "key");
+        assert_eq!(stem("charging"), "charg");
+        assert_eq!(stem("dropped"), "dropp");
+        // Too short to strip without losing the word.
+        assert_eq!(stem("bus"), "bus");
+        assert_eq!(stem("red"), "red");
+    }
+
+    #[test]
+    fn query_terms_skip_negated_words() {
+        assert_eq!(terms("Blue Bottles -red"), ["blue", "bottl"]);
+        assert_eq!(terms("id-card"), ["id", "card"]);
+    }
+
+    #[test]
+    fn match_ranges_are_byte_offsets_of_whole_words() {
+        let text = "Lost a blue bottle, bottles near Café";
+        let ranges = match_ranges(text, &terms("bottle café"));
+        let words: Vec<&str> = ranges.iter().map(|&[start, end]| &text[start..end]).collect();
+        assert_eq!(words, ["bottle", "bottles", "Café"]);
+        assert!(match_ranges(text, &terms("keys")).is_empty());
+    }
+
+    #[test]
+    fn highlight_escapes_user_text() {
+        let text = "<img src=x onerror=alert(1)> wallet & keys";
+        let ranges = match_ranges(text, &terms("wallet"));
+        assert_eq!(
+            highlight(text, &ranges),
+            "&lt;img src=x onerror=alert(1)&gt; <mark>wallet</mark> &amp; keys"
+        );
+    }
+
+    #[test]
+    fn highlight_without_matches_only_escapes() {
+        assert_eq!(highlight("a \"quote\"", &[]), "a &quot;quote&quot;");
+    }
+}
@@ -0,0 +1,76 @@
+use super::{Storage, StoredObject};
+use crate::{Error, Result};
+use async_trait::async_trait;
+use std::io::ErrorKind;
+use std::path::{Component, Path, PathBuf};
+
+/// Keeps files under a directory on the server's disk. The content type is
+/// stored next to each file, since keys don't have to carry an extension.
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    fn path_for(&self, key: &str) -> Result<PathBuf> {
+        let relative = Path::new(key);
+        // Keys are generated by us, but never let one escape the root.
+        if !relative.components().all(|part| matches!(part, Component::Normal(_))) {
+            return Err(Error::InvalidInput(format!("Invalid storage key `{}`", key)));
+        }
+        Ok(self.root.join(relative))
+    }
+}
+
+fn content_type_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".content-type");
+    PathBuf::from(name)
+}
+
+fn io_error(e: std::io::Error) -> Error {
+    Error::StorageError(e.to_string())
+}
+
+#[async_trait]
+impl Storage for LocalStorage {
+    async fn put(&self, key: &str, bytes: Vec<u8>, content_type: &str) -> Result<()> {
+        let path = self.path_for(key)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await.map_err(io_error)?;
+        }
+        tokio::fs::write(&path, bytes).await.map_err(io_error)?;
+        tokio::fs::write(content_type_path(&path), content_type)
+            .await
+            .map_err(io_error)
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<StoredObject>> {
+        let path = self.path_for(key)?;
+        let bytes = match tokio::fs::read(&path).await {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(io_error(e)),
+        };
+        let content_type = tokio::fs::read_to_string(content_type_path(&path))
+            .await
+            .unwrap_or_else(|_| "application/octet-stream".to_string());
+
+        Ok(Some(StoredObject { bytes, content_type }))
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        let path = self.path_for(key)?;
+        for path in [content_type_path(&path), path] {
+            match tokio::fs::remove_file(&path).await {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => return Err(io_error(e)),
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,63 @@
+//! Where uploaded files live. Handlers only see the [`Storage`] trait, so
+//! the backend is picked from the config at startup.
+
+mod local;
+mod s3;
+
+use crate::config::{StorageBackend, StorageConfig};
+use crate::Result;
+use async_trait::async_trait;
+use std::sync::Arc;
+
+pub use local::LocalStorage;
+pub use s3::S3Storage;
+
+#[derive(Debug, Clone)]
+pub struct StoredObject {
+    pub bytes: Vec<u8>,
+    pub content_type: String,
+}
+
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Stores `bytes` under `key`, replacing anything already there.
+    async fn put(&self, key: &str, bytes: Vec<u8>, content_type: &str) -> Result<()>;
+
+    /// `None` when nothing is stored under `key`.
+    async fn get(&self, key: &str) -> Result<Option<StoredObject>>;
+
+    async fn delete(&self, key: &str) -> Result<()>;
+}
+
+pub fn from_config(config: &StorageConfig) -> Arc<dyn Storage> {
+    match &config.backend {
+        StorageBackend::Local { path } => Arc::new(LocalStorage::new(path.clone())),
+        StorageBackend::S3(s3) => Arc::new(S3Storage::new(s3.clone())),
+    }
+}
+
+/// Image formats accepted for upload, recognised by their magic bytes
+/// rather than the client's content type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Jpeg,
+    Png,
+    Webp,
+    Gif,
+}
+
+impl ImageFormat {
+    pub fn sniff(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+            Some(ImageFormat::Jpeg)
+        } else if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+            Some(ImageFormat::Png)
+        } else if bytes.len() >= 12 && &bytes[..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+            Some(ImageFormat::Webp)
+        } else if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+            Some(ImageFormat::Gif)
+        } else {
+            None
+        }
+    }
+}
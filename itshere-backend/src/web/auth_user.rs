@@ -0,0 +1,66 @@
+use crate::{
+    Error,
+    auth::{Claims, verify_jwt},
+    model::ModelController,
+    web::{AUTH_TOKEN, LEGACY_AUTH_TOKENS},
+};
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts},
+    http::{header::AUTHORIZATION, request::Parts},
+};
+use std::sync::Arc;
+use tower_cookies::Cookies;
+
+/// Authenticated caller, resolved from an `Authorization: Bearer` header or
+/// the auth cookie. Add it to a handler's arguments to make the route
+/// require a valid token belonging to a session that has not been revoked.
+#[derive(Debug)]
+pub struct AuthUser(pub Claims);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for AuthUser
+where
+    S: Send + Sync,
+    Arc<ModelController>: FromRef<S>,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let token = match bearer_token(parts) {
+            Some(token) => token,
+            None => cookie_token(parts, state).await.ok_or(Error::Unauthorized)?,
+        };
+
+        let controller = Arc::<ModelController>::from_ref(state);
+        authenticate(&controller, &token).await.map(AuthUser)
+    }
+}
+
+/// Checks a raw access token, for callers that get it some other way than
+/// a header or cookie (e.g. WebSocket clients, which can't set headers).
+pub async fn authenticate(controller: &ModelController, token: &str) -> Result<Claims, Error> {
+    let claims = verify_jwt(token)?;
+    if !controller.is_session_active(&claims.sid).await? {
+        return Err(Error::InvalidToken);
+    }
+    Ok(claims)
+}
+
+fn bearer_token(parts: &Parts) -> Option<String> {
+    let value = parts.headers.get(AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = value.split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("bearer") {
+        return None;
+    }
+    let token = token.trim();
+    (!token.is_empty()).then(|| token.to_string())
+}
+
+async fn cookie_token<S: Send + Sync>(parts: &mut Parts, state: &S) -> Option<String> {
+    let cookies = Cookies::from_request_parts(parts, state).await.ok()?;
+    std::iter::once(AUTH_TOKEN)
+        .chain(LEGACY_AUTH_TOKENS)
+        .find_map(|name| cookies.get(name))
+        .map(|cookie| cookie.value().to_string())
+}
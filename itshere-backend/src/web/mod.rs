@@ -1,5 +1,12 @@
+pub mod auth_user;
 pub mod routes_login;
 pub mod routes_post;
 pub mod route_edit;
 pub mod routes_chat;
-pub const AUTH_TOKEN:&str = "AUTH-TOKEN";
\ No newline at end of file
+pub mod routes_claim;
+pub mod routes_location;
+pub mod routes_upload;
+pub const AUTH_TOKEN:&str = "AUTH-TOKEN";
+pub const REFRESH_TOKEN:&str = "REFRESH-TOKEN";
+/// Cookie names the mobile client has sent in the past; still accepted.
+pub const LEGACY_AUTH_TOKENS: [&str; 2] = ["auth-token", "auth_token"];
@@ -1,17 +1,12 @@
-use crate::{
-    auth::{Claims, verify_jwt},
-    model::{Comment, MissingPost, ModelController, PostWithUser},
-    web::AUTH_TOKEN,
-};
+use crate::{model::ModelController, web::auth_user::AuthUser};
 use axum::http::StatusCode;
 use axum::{
     Json, Router,
     extract::{Path, State},
 };
-use mongodb::bson::{Uuid, doc};
+use mongodb::bson::doc;
 use serde_json::{Value, json};
 use std::sync::Arc;
-use tower_cookies::Cookies;
 
 pub fn routes(controller: Arc<ModelController>) -> Router {
     Router::new()
@@ -27,14 +22,8 @@ pub fn routes(controller: Arc<ModelController>) -> Router {
 pub async fn editgmail(
     State(controller): State<Arc<ModelController>>,
     Path(gmail): Path<String>,
-    cookies: Cookies,
+    AuthUser(claims): AuthUser,
 ) -> Result<Json<Value>, StatusCode> {
-    let token = cookies
-        .get(AUTH_TOKEN)
-        .ok_or(StatusCode::UNAUTHORIZED)?
-        .value()
-        .to_string();
-    let claims = verify_jwt(&token).map_err(|_| StatusCode::UNAUTHORIZED)?;
     let u_name = claims.sub;
     println!(" {}", u_name);
     let user = controller
@@ -53,14 +42,8 @@ pub async fn editgmail(
 pub async fn edit_phonenumber(
     State(controller): State<Arc<ModelController>>,
     Path(phone): Path<String>,
-    cookies: Cookies,
+    AuthUser(claims): AuthUser,
 ) -> Result<Json<Value>, StatusCode> {
-    let token = cookies
-        .get(AUTH_TOKEN)
-        .ok_or(StatusCode::UNAUTHORIZED)?
-        .value()
-        .to_string();
-    let claims = verify_jwt(&token).map_err(|_| StatusCode::UNAUTHORIZED)?;
     let u_name = claims.sub;
     println!(" {}", u_name);
     let user = controller
@@ -81,15 +64,9 @@ pub async fn edit_phonenumber(
 pub async fn getuserpost(
     State(controller): State<Arc<ModelController>>,
     Path(username): Path<String>,
-    cookies: Cookies,
+    _auth: AuthUser,
 ) -> Result<Json<Value>, StatusCode> {
-    let token = cookies
-        .get(AUTH_TOKEN)
-        .ok_or(StatusCode::UNAUTHORIZED)?
-        .value()
-        .to_string();
-    let Claims = verify_jwt(&token).map_err(|_| StatusCode::UNAUTHORIZED)?;
-    let user = controller
+    controller
         .user_collection
         .find_one(doc! { "username": &username }, None)
         .await
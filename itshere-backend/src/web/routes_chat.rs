@@ -1,18 +1,28 @@
 use crate::{
-    auth::{Claims, verify_jwt},
-    model::{Comment, MissingPost, ModelController, PostWithUser},
-    web::AUTH_TOKEN,
+    Error,
+    auth::Claims,
+    chat_hub::Subscription,
+    model::{MessageQuery, ModelController},
+    web::auth_user::{AuthUser, authenticate},
 };
 use serde::{Deserialize,Serialize};
 use axum::http::StatusCode;
 use axum::{
     Json, Router,
-    extract::{Path, State},
+    extract::{
+        Path, Query, State,
+        ws::{Message as WsMessage, WebSocket, WebSocketUpgrade},
+    },
+    response::Response,
 };
-use mongodb::bson::{Uuid, doc};
 use serde_json::{Value, json};
 use std::sync::Arc;
-use tower_cookies::Cookies;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::{Instant, interval_at, sleep_until};
+
+/// How often an idle socket re-checks that its session wasn't revoked.
+const SESSION_CHECK_INTERVAL: Duration = Duration::from_secs(30);
 
 pub fn routes(controller: Arc<ModelController>) -> Router {
     Router::new()
@@ -20,26 +30,32 @@ pub fn routes(controller: Arc<ModelController>) -> Router {
         .route("/api/chat/:username", axum::routing::post(send_message))
         .route("/api/chat/:username", axum::routing::get(get_chat))
         .route("/api/getchat", axum::routing::get(getallchatrooms))
+        .route("/api/getchat/unread", axum::routing::get(unread_total))
+        .route("/api/chat/rooms/:room_id/read", axum::routing::put(mark_read))
+        .route("/api/ws/chat", axum::routing::get(chat_socket))
+        .route("/api/posts/:post_id/chats", axum::routing::get(post_chats))
         .with_state(controller)
 }
+/// Picks the room about a post (`?post_id=`) rather than the general one
+/// between the two users.
+#[derive(Deserialize)]
+pub struct ChatTarget {
+    pub post_id: Option<String>,
+}
+
 pub async fn create_chat(
     State(controller): State<Arc<ModelController>>,
     Path(username): Path<String>,
-    cookies: Cookies,
+    AuthUser(claims): AuthUser,
+    Query(target): Query<ChatTarget>,
 ) -> Result<Json<Value>, StatusCode> {
-    let token = cookies
-        .get(AUTH_TOKEN)
-        .ok_or(StatusCode::UNAUTHORIZED)?
-        .value()
-        .to_string();
-    let claims = verify_jwt(&token).map_err(|_| StatusCode::UNAUTHORIZED)?;
     let u_name = claims.sub;
     println!(" {}", u_name);
-    controller
-        .create_or_get_chat(u_name, username)
+    let chat_room = controller
+        .create_or_get_chat(u_name, username, target.post_id.as_deref())
         .await
         .map_err(|_| StatusCode::BAD_REQUEST)?;
-    Ok(Json(json!({ "status": "chat created successfully" })))
+    Ok(Json(json!({ "status": "chat created successfully", "room": chat_room })))
 }
 #[derive(Deserialize, Serialize)]
 pub struct Message {
@@ -49,27 +65,25 @@ pub struct Message {
 pub async fn send_message(
     State(controller): State<Arc<ModelController>>,
     Path(username): Path<String>,
-    cookies: Cookies,
+    AuthUser(claims): AuthUser,
+    Query(target): Query<ChatTarget>,
     Json(payload): Json<Message>,
 ) -> Result<Json<Value>, StatusCode> {
    
-    let token = cookies
-        .get(AUTH_TOKEN)
-        .ok_or(StatusCode::UNAUTHORIZED)?
-        .value()
-        .to_string();
-    let claims = verify_jwt(&token).map_err(|_| StatusCode::UNAUTHORIZED)?;
     let u_name = claims.sub;
     
     println!("Sending message from {} to {}: {}", 
         u_name, username, payload.message);
     
     
-    let chat_room = match controller.create_or_get_chat(u_name.clone(), username).await {
+    let chat_room = match controller
+        .create_or_get_chat(u_name.clone(), username, target.post_id.as_deref())
+        .await
+    {
         Ok(room) => room,
         Err(e) => {
             eprintln!("Error creating/getting chat: {:?}", e);
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            return Err(e.status_and_message().0);
         }
     };
     
@@ -95,31 +109,32 @@ pub async fn send_message(
         },
         Err(e) => {
             eprintln!("Error sending message: {:?}", e);
-            Err(StatusCode::INTERNAL_SERVER_ERROR)
+            Err(e.status_and_message().0)
         }
     }
 }
 
+/// Conversation with `username`; supports `since`, `before`, `limit` and
+/// `cursor` (see [`MessageQuery`]).
 pub async fn get_chat(
     State(controller): State<Arc<ModelController>>,
     Path(username): Path<String>,
-    cookies: Cookies,
+    AuthUser(claims): AuthUser,
+    Query(target): Query<ChatTarget>,
+    Query(query): Query<MessageQuery>,
 ) -> Result<Json<Value>, StatusCode> {
-    let token = cookies
-        .get(AUTH_TOKEN)
-        .ok_or(StatusCode::UNAUTHORIZED)?
-        .value()
-        .to_string();
-    let claims = verify_jwt(&token).map_err(|_| StatusCode::UNAUTHORIZED)?;
     let u_name = claims.sub;
     
     println!("Getting chat for {} with {}", u_name, username);
     
-    let chat_room = match controller.create_or_get_chat(u_name.clone(), username).await {
+    let chat_room = match controller
+        .create_or_get_chat(u_name.clone(), username, target.post_id.as_deref())
+        .await
+    {
         Ok(room) => room,
         Err(e) => {
             eprintln!("Error creating/getting chat: {:?}", e);
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            return Err(e.status_and_message().0);
         }
     };
     
@@ -133,14 +148,32 @@ pub async fn get_chat(
     
     println!("Got chat ID: {}", chat_id);
     
-    match controller.get_chat_messages(&chat_id).await {
-        Ok(messages) => {
+    let post = match chat_room.post_id {
+        Some(post_id) => match controller.post_summaries(&[post_id]).await {
+            Ok(mut posts) => posts.remove(&post_id),
+            Err(e) => {
+                eprintln!("Error loading chat post: {:?}", e);
+                return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+        },
+        None => None,
+    };
+
+    match controller.get_chat_messages(&chat_id, &query).await {
+        Ok(page) => {
             println!("Messages retrieved successfully");
             Ok(Json(json!({ 
                 "status": "success", 
-                "messages": messages
+                "room": chat_room,
+                "post": post,
+                "messages": page.messages,
+                "next_cursor": page.next_cursor
             })))
         },
+        Err(Error::InvalidInput(message)) => {
+            eprintln!("Invalid chat history query: {}", message);
+            Err(StatusCode::BAD_REQUEST)
+        }
         Err(e) => {
             eprintln!("Error retrieving messages: {:?}", e);
             Err(StatusCode::INTERNAL_SERVER_ERROR)
@@ -149,19 +182,13 @@ pub async fn get_chat(
 }
 pub async fn  getallchatrooms(
     State(controller): State<Arc<ModelController>>,
-    cookies: Cookies,
+    AuthUser(claims): AuthUser,
 ) -> Result<Json<Value>, StatusCode> {
-    let token = cookies
-        .get(AUTH_TOKEN)
-        .ok_or(StatusCode::UNAUTHORIZED)?
-        .value()
-        .to_string();
-    let claims = verify_jwt(&token).map_err(|_| StatusCode::UNAUTHORIZED)?;
     let u_name = claims.sub;
     
     println!("Getting all chat rooms for {}", u_name);
     
-    match controller.get_chat_rooms(&u_name).await {
+    match controller.get_chat_room_summaries(&u_name).await {
         Ok(rooms) => {
             println!("Chat rooms retrieved successfully");
             Ok(Json(json!({ 
@@ -174,4 +201,195 @@ pub async fn  getallchatrooms(
             Err(StatusCode::INTERNAL_SERVER_ERROR)
         }
     }
-}
\ No newline at end of file
+}
+
+#[derive(Deserialize)]
+pub struct MarkReadPayload {
+    pub message_id: String,
+}
+
+/// Marks the room read up to `message_id`.
+pub async fn mark_read(
+    State(controller): State<Arc<ModelController>>,
+    Path(room_id): Path<String>,
+    AuthUser(claims): AuthUser,
+    Json(payload): Json<MarkReadPayload>,
+) -> crate::Result<Json<Value>> {
+    let marked = controller
+        .mark_chat_read(&room_id, &claims.sub, &payload.message_id)
+        .await?;
+    Ok(Json(json!({ "status": "success", "marked_read": marked })))
+}
+
+/// Total unread messages across all rooms, for the Chats tab badge.
+pub async fn unread_total(
+    State(controller): State<Arc<ModelController>>,
+    AuthUser(claims): AuthUser,
+) -> crate::Result<Json<Value>> {
+    let counts = controller.unread_counts(&claims.sub).await?;
+    let total: u64 = counts.values().sum();
+    Ok(Json(json!({ "status": "success", "unread": total })))
+}
+
+/// Conversations about the caller's own post.
+pub async fn post_chats(
+    State(controller): State<Arc<ModelController>>,
+    Path(post_id): Path<String>,
+    AuthUser(claims): AuthUser,
+) -> crate::Result<Json<Value>> {
+    let rooms = controller.get_post_chat_rooms(&post_id, &claims.sub).await?;
+    Ok(Json(json!({ "status": "success", "chat_rooms": rooms })))
+}
+
+/// Opens the real-time chat socket. Browsers can't set headers on a
+/// WebSocket, so the token may also come as `?token=`.
+pub async fn chat_socket(
+    State(controller): State<Arc<ModelController>>,
+    Query(query): Query<SocketQuery>,
+    auth: Result<AuthUser, Error>,
+    ws: WebSocketUpgrade,
+) -> crate::Result<Response> {
+    let claims = match query.token {
+        Some(token) => authenticate(&controller, &token).await?,
+        None => auth?.0,
+    };
+    Ok(ws.on_upgrade(move |socket| run_socket(socket, controller, claims)))
+}
+
+#[derive(Deserialize)]
+pub struct SocketQuery {
+    pub token: Option<String>,
+}
+
+/// Frames clients send over the socket.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientEvent {
+    /// Same as `POST /api/chat/:username`. `client_id` is echoed back in
+    /// the acknowledgement so the client can match it to its pending message.
+    Send {
+        to: String,
+        message: String,
+        #[serde(default)]
+        post_id: Option<String>,
+        #[serde(default)]
+        client_id: Option<String>,
+    },
+}
+
+/// Pushes hub events for the user until either side hangs up, and sends
+/// the messages the client writes. The hub is keyed by user, so rooms
+/// created after connecting are covered too. The socket closes when the
+/// token expires or the session is revoked, even if the client only
+/// listens.
+async fn run_socket(mut socket: WebSocket, controller: Arc<ModelController>, claims: Claims) {
+    let mut events = Subscription::new(controller.hub.clone(), &claims.sub);
+
+    let now = chrono::Utc::now().timestamp().max(0) as u64;
+    let lifetime = Duration::from_secs((claims.exp as u64).saturating_sub(now));
+    let expires = sleep_until(Instant::now() + lifetime);
+    tokio::pin!(expires);
+    let mut session_check = interval_at(Instant::now() + SESSION_CHECK_INTERVAL, SESSION_CHECK_INTERVAL);
+
+    let rooms: Vec<String> = match controller.get_chat_rooms(&claims.sub).await {
+        Ok(rooms) => rooms.iter().filter_map(|room| room.id).map(|id| id.to_hex()).collect(),
+        Err(e) => {
+            eprintln!("Error loading chat rooms for {}: {:?}", claims.sub, e);
+            return;
+        }
+    };
+    if send_json(&mut socket, &json!({ "type": "ready", "rooms": rooms })).await.is_err() {
+        return;
+    }
+
+    loop {
+        tokio::select! {
+            _ = &mut expires => {
+                end_session(&mut socket).await;
+                break;
+            }
+            _ = session_check.tick() => {
+                match controller.is_session_active(&claims.sid).await {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        end_session(&mut socket).await;
+                        break;
+                    }
+                    Err(e) => eprintln!("Error checking session for {}: {:?}", claims.sub, e),
+                }
+            }
+            event = events.recv() => {
+                let sent = match event {
+                    Ok(event) => send_json(&mut socket, &event).await,
+                    // The client missed messages; it should refetch history.
+                    Err(RecvError::Lagged(skipped)) => {
+                        send_json(&mut socket, &json!({ "type": "lagged", "skipped": skipped })).await
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                if sent.is_err() {
+                    break;
+                }
+            }
+            incoming = socket.recv() => {
+                let text = match incoming {
+                    Some(Ok(WsMessage::Text(text))) => text,
+                    Some(Ok(WsMessage::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => continue,
+                };
+                let reply = match handle_client_event(&controller, &claims, &text).await {
+                    Ok(reply) => reply,
+                    Err(Error::InvalidToken) => {
+                        end_session(&mut socket).await;
+                        break;
+                    }
+                    Err(e) => json!({ "type": "error", "error": e.status_and_message().1 }),
+                };
+                if send_json(&mut socket, &reply).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+async fn handle_client_event(
+    controller: &ModelController,
+    claims: &Claims,
+    text: &str,
+) -> crate::Result<Value> {
+    let event: ClientEvent = serde_json::from_str(text)
+        .map_err(|e| Error::InvalidInput(format!("Invalid frame: {}", e)))?;
+    // Sockets live longer than requests; stop honouring revoked sessions.
+    if !controller.is_session_active(&claims.sid).await? {
+        return Err(Error::InvalidToken);
+    }
+
+    match event {
+        ClientEvent::Send { to, message, post_id, client_id } => {
+            let chat_room = controller
+                .create_or_get_chat(claims.sub.clone(), to, post_id.as_deref())
+                .await?;
+            let chat_id = chat_room.id.ok_or(Error::ChatNotFound)?.to_hex();
+            let message = controller.send_message(&chat_id, claims.sub.clone(), message).await?;
+
+            Ok(json!({
+                "type": "sent",
+                "client_id": client_id,
+                "room_id": chat_id,
+                "message_id": message.id.map(|id| id.to_hex())
+            }))
+        }
+    }
+}
+
+/// Tells the client its session is over and closes the socket.
+async fn end_session(socket: &mut WebSocket) {
+    let _ = send_json(socket, &json!({ "type": "error", "error": "Session has ended" })).await;
+    let _ = socket.send(WsMessage::Close(None)).await;
+}
+
+async fn send_json<T: Serialize>(socket: &mut WebSocket, value: &T) -> Result<(), axum::Error> {
+    let text = serde_json::to_string(value).expect("chat events serialize");
+    socket.send(WsMessage::Text(text)).await
+}
@@ -0,0 +1,192 @@
+use crate::{
+    model::{ClaimStatus, ModelController},
+    web::auth_user::AuthUser,
+    Result,
+};
+use axum::{
+    Json, Router,
+    extract::{Path, Query, State},
+    routing::{get, put},
+};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::sync::Arc;
+
+pub fn routes(controller: Arc<ModelController>) -> Router {
+    Router::new()
+        .route("/api/posts/:post_id/questions", get(get_questions))
+        .route(
+            "/api/posts/:post_id/claims",
+            get(get_post_claims).post(create_claim),
+        )
+        .route("/api/claims/mine", get(get_my_claims))
+        .route("/api/claims/:claim_id", put(review_claim))
+        .route(
+            "/api/claims/:claim_id/handover",
+            get(get_handover).post(confirm_handover),
+        )
+        .route("/api/handovers/mine", get(get_handover_history))
+        .route("/api/stats/returns", get(get_return_stats))
+        .with_state(controller)
+}
+
+pub async fn get_questions(
+    State(controller): State<Arc<ModelController>>,
+    Path(post_id): Path<String>,
+    _auth: AuthUser,
+) -> Result<Json<Value>> {
+    let questions = controller.get_verification_questions(&post_id).await?;
+
+    Ok(Json(json!({
+        "status": "success",
+        "questions": questions
+    })))
+}
+
+pub async fn create_claim(
+    State(controller): State<Arc<ModelController>>,
+    Path(post_id): Path<String>,
+    AuthUser(claims): AuthUser,
+    Json(payload): Json<ClaimPayload>,
+) -> Result<Json<Value>> {
+    let claim = controller
+        .create_claim(&post_id, &claims.sub, payload.answers, payload.message)
+        .await?;
+
+    Ok(Json(json!({
+        "status": "success",
+        "claim": claim
+    })))
+}
+
+/// The finder's review queue; pending claims unless `?status=` says otherwise.
+pub async fn get_post_claims(
+    State(controller): State<Arc<ModelController>>,
+    Path(post_id): Path<String>,
+    AuthUser(claims): AuthUser,
+    Query(query): Query<ClaimQuery>,
+) -> Result<Json<Value>> {
+    let status = query.status.unwrap_or(ClaimStatus::Pending);
+    let post_claims = controller
+        .get_post_claims(&post_id, &claims.sub, Some(status))
+        .await?;
+
+    Ok(Json(json!({
+        "status": "success",
+        "count": post_claims.len(),
+        "claims": post_claims
+    })))
+}
+
+pub async fn get_my_claims(
+    State(controller): State<Arc<ModelController>>,
+    AuthUser(claims): AuthUser,
+) -> Result<Json<Value>> {
+    let my_claims = controller.get_claims_by_claimant(&claims.sub).await?;
+
+    Ok(Json(json!({
+        "status": "success",
+        "count": my_claims.len(),
+        "claims": my_claims
+    })))
+}
+
+pub async fn review_claim(
+    State(controller): State<Arc<ModelController>>,
+    Path(claim_id): Path<String>,
+    AuthUser(claims): AuthUser,
+    Json(payload): Json<ReviewPayload>,
+) -> Result<Json<Value>> {
+    let accept = matches!(payload.decision, Decision::Accept);
+    let claim = controller.review_claim(&claim_id, &claims.sub, accept).await?;
+
+    Ok(Json(json!({
+        "status": "success",
+        "claim": claim
+    })))
+}
+
+/// The owner gets the code and QR payload to show the finder; the finder
+/// only sees the handover's status.
+pub async fn get_handover(
+    State(controller): State<Arc<ModelController>>,
+    Path(claim_id): Path<String>,
+    AuthUser(claims): AuthUser,
+) -> Result<Json<Value>> {
+    let handover = controller.get_handover(&claim_id, &claims.sub).await?;
+
+    Ok(Json(json!({
+        "status": "success",
+        "qr_payload": handover.qr_payload(),
+        "handover": handover
+    })))
+}
+
+pub async fn confirm_handover(
+    State(controller): State<Arc<ModelController>>,
+    Path(claim_id): Path<String>,
+    AuthUser(claims): AuthUser,
+    Json(payload): Json<HandoverPayload>,
+) -> Result<Json<Value>> {
+    let handover = controller
+        .confirm_handover(&claim_id, &claims.sub, &payload.code)
+        .await?;
+
+    Ok(Json(json!({
+        "status": "success",
+        "handover": handover
+    })))
+}
+
+pub async fn get_handover_history(
+    State(controller): State<Arc<ModelController>>,
+    AuthUser(claims): AuthUser,
+) -> Result<Json<Value>> {
+    let handovers = controller.get_handover_history(&claims.sub).await?;
+
+    Ok(Json(json!({
+        "status": "success",
+        "count": handovers.len(),
+        "handovers": handovers
+    })))
+}
+
+pub async fn get_return_stats(
+    State(controller): State<Arc<ModelController>>,
+) -> Result<Json<Value>> {
+    let stats = controller.return_stats().await?;
+
+    Ok(Json(json!({
+        "status": "success",
+        "stats": stats
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ClaimPayload {
+    #[serde(default)]
+    pub answers: Vec<String>,
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ClaimQuery {
+    pub status: Option<ClaimStatus>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Decision {
+    Accept,
+    Reject,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReviewPayload {
+    pub decision: Decision,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct HandoverPayload {
+    pub code: String,
+}
@@ -0,0 +1,107 @@
+use crate::{
+    model::{DEFAULT_LOCATION_LIMIT, LocationInput, ModelController},
+    web::auth_user::AuthUser,
+    Error, Result,
+};
+use axum::{
+    Json, Router,
+    extract::{Path, Query, State},
+    routing::{get, post, put},
+};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::sync::Arc;
+
+pub fn routes(controller: Arc<ModelController>) -> Router {
+    Router::new()
+        .route("/api/locations", get(list_locations))
+        .route("/api/locations/:location_id", get(get_location))
+        .route("/api/admin/locations", post(create_location))
+        .route(
+            "/api/admin/locations/:location_id",
+            put(update_location).delete(delete_location),
+        )
+        .with_state(controller)
+}
+
+/// Lists the directory, or autocompletes it when `q` is given.
+pub async fn list_locations(
+    State(controller): State<Arc<ModelController>>,
+    Query(query): Query<LocationQuery>,
+) -> Result<Json<Value>> {
+    let limit = query.limit.unwrap_or(DEFAULT_LOCATION_LIMIT);
+    let locations = controller.list_locations(query.q.as_deref(), limit).await?;
+
+    Ok(Json(json!({
+        "status": "success",
+        "count": locations.len(),
+        "locations": locations
+    })))
+}
+
+pub async fn get_location(
+    State(controller): State<Arc<ModelController>>,
+    Path(location_id): Path<String>,
+) -> Result<Json<Value>> {
+    let location = controller.find_location(&location_id).await?;
+
+    Ok(Json(json!({
+        "status": "success",
+        "location": location
+    })))
+}
+
+pub async fn create_location(
+    State(controller): State<Arc<ModelController>>,
+    AuthUser(claims): AuthUser,
+    Json(payload): Json<LocationInput>,
+) -> Result<Json<Value>> {
+    if !controller.is_admin(&claims.sub).await? {
+        return Err(Error::Forbidden);
+    }
+    let location = controller.create_location(payload).await?;
+
+    Ok(Json(json!({
+        "status": "success",
+        "location": location
+    })))
+}
+
+pub async fn update_location(
+    State(controller): State<Arc<ModelController>>,
+    Path(location_id): Path<String>,
+    AuthUser(claims): AuthUser,
+    Json(payload): Json<LocationInput>,
+) -> Result<Json<Value>> {
+    if !controller.is_admin(&claims.sub).await? {
+        return Err(Error::Forbidden);
+    }
+    let location = controller.update_location(&location_id, payload).await?;
+
+    Ok(Json(json!({
+        "status": "success",
+        "location": location
+    })))
+}
+
+pub async fn delete_location(
+    State(controller): State<Arc<ModelController>>,
+    Path(location_id): Path<String>,
+    AuthUser(claims): AuthUser,
+) -> Result<Json<Value>> {
+    if !controller.is_admin(&claims.sub).await? {
+        return Err(Error::Forbidden);
+    }
+    controller.delete_location(&location_id).await?;
+
+    Ok(Json(json!({
+        "status": "success",
+        "message": "Location deleted"
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LocationQuery {
+    pub q: Option<String>,
+    pub limit: Option<i64>,
+}
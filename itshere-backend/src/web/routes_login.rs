@@ -1,18 +1,21 @@
-use crate::{auth::create_jwt, Error, Result};
+use crate::{auth::{create_jwt, hash_password, verify_password, PasswordMatch}, Error, Result};
 use serde::Deserialize;
 use axum::{Json, Router};
 use serde_json::{json, Value};
 use axum::routing::post;
 use tower_cookies::{Cookie, Cookies};
-use crate::model::{ModelController, User_Register};
+use crate::model::{IssuedSession, ModelController, UserPublic, UserRegister};
 use std::sync::Arc;
 use axum::extract::State;
-use crate::web::AUTH_TOKEN;
+use crate::web::{auth_user::AuthUser, AUTH_TOKEN, REFRESH_TOKEN};
 
 pub fn routes(controller: Arc<ModelController>) -> Router {
     Router::new()
         .route("/api/login", post(api_login))
         .route("/api/register", post(create_user))
+        .route("/api/refresh", post(api_refresh))
+        .route("/api/logout", post(api_logout))
+        .route("/api/logout-all", post(api_logout_all))
         .with_state(controller) 
 }
 
@@ -26,18 +29,82 @@ pub async fn api_login(
         .await?
         .ok_or(Error::LoginFail)?; 
 
-    if user.password != payload.pwd {
-        return Err(Error::LoginFail);
+    match verify_password(&payload.pwd, &user.password) {
+        PasswordMatch::Valid => {}
+        PasswordMatch::NeedsRehash => {
+            let rehashed = hash_password(&payload.pwd)?;
+            if let Err(e) = controller.update_password_hash(&user.username, &rehashed).await {
+                eprintln!("Failed to rehash password for {}: {:?}", user.username, e);
+            }
+        }
+        PasswordMatch::Invalid => return Err(Error::LoginFail),
     }
 
-    let token = create_jwt(&payload.username)?;
-    cookies.add(Cookie::new(AUTH_TOKEN, token.clone()));
+    let session = controller.create_session(&user.username).await?;
+    let token = issue_tokens(&cookies, &session)?;
+    let user = UserPublic::from(user);
 
     Ok(Json(json!({
         "result": {
             "user":user,
             "success": true,
-            "token": token
+            "token": token,
+            "refresh_token": session.refresh_token
+        }
+    })))
+}
+
+/// Trades a refresh token (from the body or the refresh cookie) for a new
+/// access token and a rotated refresh token.
+pub async fn api_refresh(
+    State(controller): State<Arc<ModelController>>,
+    cookies: Cookies,
+    payload: Option<Json<RefreshPayload>>,
+) -> Result<Json<Value>> {
+    let refresh_token = payload
+        .and_then(|Json(payload)| payload.refresh_token)
+        .or_else(|| cookies.get(REFRESH_TOKEN).map(|c| c.value().to_string()))
+        .ok_or(Error::Unauthorized)?;
+
+    let session = controller.rotate_session(&refresh_token).await?;
+    let token = issue_tokens(&cookies, &session)?;
+
+    Ok(Json(json!({
+        "result": {
+            "success": true,
+            "token": token,
+            "refresh_token": session.refresh_token
+        }
+    })))
+}
+
+pub async fn api_logout(
+    State(controller): State<Arc<ModelController>>,
+    cookies: Cookies,
+    AuthUser(claims): AuthUser,
+) -> Result<Json<Value>> {
+    controller.revoke_session(&claims.sid, &claims.sub).await?;
+    clear_tokens(&cookies);
+
+    Ok(Json(json!({
+        "result": {
+            "success": true
+        }
+    })))
+}
+
+pub async fn api_logout_all(
+    State(controller): State<Arc<ModelController>>,
+    cookies: Cookies,
+    AuthUser(claims): AuthUser,
+) -> Result<Json<Value>> {
+    let revoked = controller.revoke_all_sessions(&claims.sub).await?;
+    clear_tokens(&cookies);
+
+    Ok(Json(json!({
+        "result": {
+            "success": true,
+            "revoked_sessions": revoked
         }
     })))
 }
@@ -45,7 +112,7 @@ pub async fn api_login(
 async fn create_user(
     State(controller): State<Arc<ModelController>>,
     cookies: Cookies,
-    Json(payload): Json<User_Register>,
+    Json(payload): Json<UserRegister>,
 ) -> Result<Json<Value>> {
     controller.register_user(payload).await?;
     cookies.add(Cookie::new(AUTH_TOKEN, "registered"));
@@ -56,8 +123,31 @@ async fn create_user(
         }
     })))
 }
+
+fn issue_tokens(cookies: &Cookies, session: &IssuedSession) -> Result<String> {
+    let token = create_jwt(&session.username, &session.session_id.to_hex())?;
+    cookies.add(Cookie::new(AUTH_TOKEN, token.clone()));
+    cookies.add(
+        Cookie::build(REFRESH_TOKEN, session.refresh_token.clone())
+            .path("/api")
+            .http_only(true)
+            .finish(),
+    );
+    Ok(token)
+}
+
+fn clear_tokens(cookies: &Cookies) {
+    cookies.remove(Cookie::named(AUTH_TOKEN));
+    cookies.remove(Cookie::build(REFRESH_TOKEN, "").path("/api").finish());
+}
+
 #[derive(Debug, Deserialize)]
 pub struct LoginPayload {
     username: String,
     pwd: String,
 }
+
+#[derive(Debug, Deserialize)]
+pub struct RefreshPayload {
+    refresh_token: Option<String>,
+}
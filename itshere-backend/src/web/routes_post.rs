@@ -1,24 +1,32 @@
 use crate::{
     Error,
-    auth::verify_jwt,
-    model::{Comment, MissingPost, ModelController, PostWithUser},
-    web::AUTH_TOKEN,
+    matching,
+    model::{
+        Category, Comment, Coordinates, DEFAULT_NEAR_RADIUS_M, ItemAttributes, MAX_NEAR_RADIUS_M,
+        MAX_PAGE_SIZE, MissingPost, ModelController, PageRequest, PostFilter, PostKind, PostPage,
+        PostStatus, PostUpdate, PostWithUser, timestamp, validate_post_date, validate_questions,
+    },
+    search::{SearchResult, query_terms},
+    web::auth_user::AuthUser,
 };
 use axum::http::StatusCode;
 use axum::{
     Json, Router,
-    extract::{Path, State},
+    extract::{Path, Query, State},
 };
 use serde::{Deserialize, Serialize};
-use mongodb::bson::Uuid;
+use mongodb::bson::{Uuid, oid::ObjectId};
 use serde_json::{Value, json};
 use std::sync::Arc;
-use tower_cookies::Cookies;
 
 pub fn routes(controller: Arc<ModelController>) -> Router {
     Router::new()
         .route("/api/createpost", axum::routing::post(createpost))
-        .route("/api/getallposts", axum::routing::get(get_posts))
+        .route("/api/getallposts", axum::routing::get(get_all_posts))
+        .route("/api/posts", axum::routing::get(get_posts))
+        .route("/api/posts/search", axum::routing::get(search_posts))
+        .route("/api/posts/near", axum::routing::get(posts_near))
+        .route("/api/categories", axum::routing::get(get_categories))
         .route(
             "/api/posts/:post_id/comments",
             axum::routing::post(post_comment),
@@ -27,58 +35,255 @@ pub fn routes(controller: Arc<ModelController>) -> Router {
             "/api/posts/:post_id/getcomments",
             axum::routing::get(get_all_comment),
         )
+        .route(
+            "/api/posts/:post_id",
+            axum::routing::put(update_post).delete(delete_post),
+        )
+        .route(
+            "/api/posts/:post_id/status",
+            axum::routing::put(update_status),
+        )
+        .route(
+            "/api/posts/:post_id/matches",
+            axum::routing::get(get_matches),
+        )
+        .route("/api/admin/posts/deleted", axum::routing::get(get_deleted_posts))
         .with_state(controller)
 }
 
 pub async fn createpost(
     State(controller): State<Arc<ModelController>>,
-    cookies: Cookies,
-    Json(mut post): Json<MissingPost>,
-) -> Result<Json<Value>, StatusCode> {
-    let token = cookies
-        .get(AUTH_TOKEN)
-        .ok_or(StatusCode::UNAUTHORIZED)?
-        .value()
-        .to_string();
+    AuthUser(claims): AuthUser,
+    Json(payload): Json<CreatePostPayload>,
+) -> crate::Result<Json<Value>> {
+    let (image_link, image_id, image_hash) = match &payload.image_id {
+        Some(image_id) => {
+            let upload = controller.find_upload(image_id, &claims.sub).await?;
+            (upload.url(), Some(upload.id), upload.image_hash)
+        }
+        None => (payload.image_link, None, None),
+    };
+    // Reposts are flagged rather than refused; the same photo may be fine
+    // for e.g. a lost and a found post.
+    let duplicate_of = match &image_hash {
+        Some(image_hash) => controller.find_duplicate_posts(&claims.sub, image_hash).await?,
+        None => vec![],
+    };
+    let location = match &payload.location_id {
+        Some(location_id) => Some(controller.find_location(location_id).await?),
+        None => None,
+    };
+    let (geo, geo_derived) = controller
+        .post_geo(payload.coordinates, location.as_ref())
+        .await?;
+    let possible_duplicates: Vec<String> = duplicate_of.iter().map(|id| id.to_hex()).collect();
+    let post = MissingPost {
+        id: None,
+        kind: payload.kind,
+        category: payload.category,
+        attributes: payload.attributes.validated()?,
+        description: payload.description,
+        date: validate_post_date(payload.date)?,
+        place: payload.place,
+        image_link,
+        user: claims.sub,
+        comments: vec![],
+        status: PostStatus::Open,
+        status_history: vec![],
+        deleted_at: None,
+        deleted_by: None,
+        location_id: location.and_then(|location| location.id),
+        geo,
+        geo_derived,
+        image_id,
+        image_hash,
+        duplicate_of,
+        verification_questions: validate_questions(payload.kind, payload.verification_questions)?,
+    };
+
+    let post_id = controller.add_post(post).await?;
+    matching::spawn_refresh(controller, post_id);
 
-    let claims = verify_jwt(&token).map_err(|_| StatusCode::UNAUTHORIZED)?;
+    Ok(Json(json!({
+        "status": "Post created successfully",
+        "post_id": post_id.to_hex(),
+        "possible_duplicates": possible_duplicates
+    })))
+}
 
-    post.user = claims.sub;
-    post.comments = vec![];
+pub async fn get_categories() -> Json<Value> {
+    let categories: Vec<&str> = Category::ALL.iter().map(|category| category.as_str()).collect();
+    Json(json!({
+        "status": "success",
+        "categories": categories
+    }))
+}
 
-    controller
-        .add_post(post)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+/// The feed as a bare array, the shape clients had before paging. Takes the
+/// same filters and paging parameters as `/api/posts`. Without a `limit`
+/// it returns every post, fetched page by page, since these clients have
+/// no way to ask for more.
+pub async fn get_all_posts(
+    State(controller): State<Arc<ModelController>>,
+    Query(filter): Query<PostFilter>,
+    Query(mut page): Query<PageRequest>,
+) -> crate::Result<Json<Vec<PostWithUser>>> {
+    if page.limit.is_some() {
+        let posts = controller.getallpost(&filter, &page).await?;
+        return Ok(Json(posts.posts));
+    }
 
-    Ok(Json(json!({ "status": "Post created successfully" })))
+    page.limit = Some(MAX_PAGE_SIZE);
+    let mut posts = Vec::new();
+    loop {
+        let next = controller.getallpost(&filter, &page).await?;
+        posts.extend(next.posts);
+        match next.next_cursor {
+            Some(cursor) => page.cursor = Some(cursor),
+            None => break,
+        }
+    }
+    Ok(Json(posts))
 }
 
+/// One page of the feed with the cursor for the next.
 pub async fn get_posts(
     State(controller): State<Arc<ModelController>>,
-) -> Result<Json<Vec<PostWithUser>>, StatusCode> {
-    match controller.getallpost().await {
-        Ok(posts) => Ok(Json(posts)),
-        Err(e) => {
-            eprintln!("Error in getallpost: {:?}", e);
-            Err(StatusCode::INTERNAL_SERVER_ERROR)
-        }
+    Query(filter): Query<PostFilter>,
+    Query(page): Query<PageRequest>,
+) -> crate::Result<Json<PostPage>> {
+    let posts = controller.getallpost(&filter, &page).await?;
+    Ok(Json(posts))
+}
+
+pub async fn search_posts(
+    State(controller): State<Arc<ModelController>>,
+    Query(search): Query<SearchQuery>,
+    Query(filter): Query<PostFilter>,
+) -> crate::Result<Json<Value>> {
+    let query = search.q.trim();
+    if query.is_empty() {
+        return Err(Error::InvalidInput("Search query is empty".to_string()));
+    }
+    let limit = search.limit.unwrap_or(20).clamp(1, 50);
+
+    let terms = query_terms(query);
+    let results: Vec<SearchResult> = controller
+        .search_posts(query, &filter, limit)
+        .await?
+        .into_iter()
+        .map(|(post, score)| SearchResult::new(post, score, &terms))
+        .collect();
+
+    Ok(Json(json!({
+        "status": "success",
+        "count": results.len(),
+        "results": results
+    })))
+}
+
+/// Posts within `radius` meters (300 by default) of a point, nearest
+/// first. Accepts the same filters as the feed.
+pub async fn posts_near(
+    State(controller): State<Arc<ModelController>>,
+    Query(near): Query<NearQuery>,
+    Query(filter): Query<PostFilter>,
+) -> crate::Result<Json<Value>> {
+    let radius = near.radius.unwrap_or(DEFAULT_NEAR_RADIUS_M);
+    if !(radius > 0.0 && radius <= MAX_NEAR_RADIUS_M) {
+        return Err(Error::InvalidInput(format!(
+            "Radius must be between 0 and {} meters",
+            MAX_NEAR_RADIUS_M
+        )));
+    }
+    let limit = near.limit.unwrap_or(20).clamp(1, 100);
+    let center = Coordinates {
+        lat: near.lat,
+        lng: near.lng,
+    };
+
+    let posts = controller.posts_near(center, radius, &filter, limit).await?;
+
+    Ok(Json(json!({
+        "status": "success",
+        "count": posts.len(),
+        "posts": posts
+    })))
+}
+
+pub async fn update_post(
+    State(controller): State<Arc<ModelController>>,
+    Path(post_id): Path<String>,
+    AuthUser(claims): AuthUser,
+    Json(update): Json<PostUpdate>,
+) -> crate::Result<Json<Value>> {
+    let post_id = controller.update_post(&post_id, &claims.sub, update).await?;
+    matching::spawn_refresh(controller, post_id);
+    Ok(Json(json!({ "status": "Post updated successfully" })))
+}
+
+pub async fn delete_post(
+    State(controller): State<Arc<ModelController>>,
+    Path(post_id): Path<String>,
+    AuthUser(claims): AuthUser,
+) -> crate::Result<Json<Value>> {
+    controller.delete_post(&post_id, &claims.sub).await?;
+    Ok(Json(json!({ "status": "Post deleted successfully" })))
+}
+
+/// Moderation view of removed posts, newest deletion first.
+pub async fn get_deleted_posts(
+    State(controller): State<Arc<ModelController>>,
+    AuthUser(claims): AuthUser,
+) -> crate::Result<Json<Value>> {
+    if !controller.is_admin(&claims.sub).await? {
+        return Err(Error::Forbidden);
     }
+    let posts = controller.get_deleted_posts().await?;
+
+    Ok(Json(json!({
+        "status": "success",
+        "count": posts.len(),
+        "posts": posts
+    })))
+}
+
+pub async fn get_matches(
+    State(controller): State<Arc<ModelController>>,
+    Path(post_id): Path<String>,
+    AuthUser(claims): AuthUser,
+) -> crate::Result<Json<Value>> {
+    let matches = controller.get_matches(&post_id, &claims.sub).await?;
+
+    Ok(Json(json!({
+        "status": "success",
+        "count": matches.len(),
+        "matches": matches
+    })))
+}
+
+pub async fn update_status(
+    State(controller): State<Arc<ModelController>>,
+    Path(post_id): Path<String>,
+    AuthUser(claims): AuthUser,
+    Json(payload): Json<StatusPayload>,
+) -> crate::Result<Json<Value>> {
+    let change = controller
+        .update_post_status(&post_id, &claims.sub, payload.status)
+        .await?;
+
+    Ok(Json(json!({
+        "status": "success",
+        "change": change
+    })))
 }
 
 pub async fn post_comment(
     State(controller): State<Arc<ModelController>>,
     Path(post_id): Path<String>,
-    cookies: Cookies,
-    Json(comment_req): Json<comment_req>,
+    AuthUser(claims): AuthUser,
+    Json(comment_req): Json<CommentReq>,
 ) -> Result<Json<Value>, StatusCode> {
-    let token = cookies
-        .get(AUTH_TOKEN)
-        .ok_or(StatusCode::UNAUTHORIZED)?
-        .value()
-        .to_string();
-
-    let claims = verify_jwt(&token).map_err(|_| StatusCode::UNAUTHORIZED)?;
     let username = claims.sub;
 
     let comment = Comment {
@@ -99,19 +304,11 @@ pub async fn post_comment(
 pub async fn get_all_comment(
     State(controller): State<Arc<ModelController>>,
     Path(post_id): Path<String>,
-    cookies: Cookies,
+    _auth: AuthUser,
 ) -> Result<Json<Value>, StatusCode> {
-    let token = cookies
-        .get(AUTH_TOKEN)
-        .ok_or(StatusCode::UNAUTHORIZED)?
-        .value()
-        .to_string();
-    
-    let claims = verify_jwt(&token).map_err(|_| StatusCode::UNAUTHORIZED)?;
-    
     println!("Fetching comments for post ID: {}", post_id);
     
-    let object_id = match mongodb::bson::oid::ObjectId::parse_str(&post_id) {
+    let object_id = match ObjectId::parse_str(&post_id) {
         Ok(oid) => oid,
         Err(e) => {
             eprintln!("Invalid ObjectId format: {}", e);
@@ -148,6 +345,51 @@ pub async fn get_all_comment(
 
 
 #[derive(Serialize, Deserialize,Debug)]
-pub struct comment_req{
+pub struct CommentReq{
     pub message:String
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreatePostPayload {
+    pub kind: PostKind,
+    #[serde(default)]
+    pub category: Category,
+    #[serde(default)]
+    pub attributes: ItemAttributes,
+    pub description: String,
+    /// ISO-8601; a bare `YYYY-MM-DD` means midnight UTC.
+    #[serde(with = "timestamp")]
+    pub date: chrono::DateTime<chrono::Utc>,
+    pub place: String,
+    #[serde(default)]
+    pub image_link: String,
+    /// Directory location; `place` then adds free-text detail.
+    pub location_id: Option<String>,
+    /// Defaults to the location's coordinates.
+    pub coordinates: Option<Coordinates>,
+    /// Id returned by the image upload endpoint; takes precedence over
+    /// `image_link`.
+    pub image_id: Option<String>,
+    /// Only allowed on found posts.
+    #[serde(default)]
+    pub verification_questions: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StatusPayload {
+    pub status: PostStatus,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SearchQuery {
+    pub q: String,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NearQuery {
+    pub lat: f64,
+    pub lng: f64,
+    pub radius: Option<f64>,
+    pub limit: Option<i64>,
+}
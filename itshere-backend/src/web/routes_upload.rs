@@ -0,0 +1,124 @@
+use crate::{
+    config::config,
+    imaging::ImageSize,
+    model::ModelController,
+    web::auth_user::AuthUser,
+    Error, Result,
+};
+use axum::{
+    Json, Router,
+    extract::{DefaultBodyLimit, Multipart, Path, State},
+    http::header,
+    response::IntoResponse,
+    routing::{get, post, put},
+};
+use serde_json::{Value, json};
+use std::sync::Arc;
+
+/// Room for the multipart framing around the file itself.
+const MULTIPART_OVERHEAD: usize = 64 * 1024;
+
+pub fn routes(controller: Arc<ModelController>) -> Router {
+    let body_limit = DefaultBodyLimit::max(config().storage.max_upload_bytes + MULTIPART_OVERHEAD);
+
+    Router::new()
+        .route("/api/uploads/images", post(upload_image))
+        .route("/api/profile-picture", put(upload_profile_picture))
+        .layer(body_limit)
+        .route("/api/images/:image_id", get(get_image))
+        .route("/api/images/:image_id/:size", get(get_image_size))
+        .with_state(controller)
+}
+
+pub async fn upload_image(
+    State(controller): State<Arc<ModelController>>,
+    AuthUser(claims): AuthUser,
+    multipart: Multipart,
+) -> Result<Json<Value>> {
+    let (bytes, content_type) = read_image(multipart).await?;
+    let upload = controller
+        .store_image(&claims.sub, bytes, content_type.as_deref())
+        .await?;
+
+    Ok(Json(json!({
+        "status": "success",
+        "image_id": upload.id.to_hex(),
+        "url": upload.url()
+    })))
+}
+
+pub async fn upload_profile_picture(
+    State(controller): State<Arc<ModelController>>,
+    AuthUser(claims): AuthUser,
+    multipart: Multipart,
+) -> Result<Json<Value>> {
+    let (bytes, content_type) = read_image(multipart).await?;
+    let upload = controller
+        .store_image(&claims.sub, bytes, content_type.as_deref())
+        .await?;
+    controller.set_profile_picture(&claims.sub, &upload.url()).await?;
+
+    Ok(Json(json!({
+        "status": "success",
+        "image_id": upload.id.to_hex(),
+        "url": upload.url()
+    })))
+}
+
+pub async fn get_image(
+    State(controller): State<Arc<ModelController>>,
+    Path(image_id): Path<String>,
+) -> Result<impl IntoResponse> {
+    serve_image(&controller, &image_id, ImageSize::Full).await
+}
+
+/// `size` is `full`, `detail` or `feed`.
+pub async fn get_image_size(
+    State(controller): State<Arc<ModelController>>,
+    Path((image_id, size)): Path<(String, String)>,
+) -> Result<impl IntoResponse> {
+    let size = ImageSize::parse(&size).ok_or(Error::ImageNotFound)?;
+    serve_image(&controller, &image_id, size).await
+}
+
+/// Images never change once uploaded, so clients may cache them forever.
+async fn serve_image(
+    controller: &ModelController,
+    image_id: &str,
+    size: ImageSize,
+) -> Result<impl IntoResponse + use<>> {
+    let image = controller.get_image(image_id, size).await?;
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, image.content_type),
+            (header::CACHE_CONTROL, "public, max-age=31536000, immutable".to_string()),
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+        ],
+        image.bytes,
+    ))
+}
+
+/// Reads the `image` (or `file`) field of a multipart body, stopping as
+/// soon as it grows past the upload limit.
+async fn read_image(mut multipart: Multipart) -> Result<(Vec<u8>, Option<String>)> {
+    let invalid = |_| Error::InvalidInput("Invalid multipart body".to_string());
+    let max = config().storage.max_upload_bytes;
+
+    while let Some(mut field) = multipart.next_field().await.map_err(invalid)? {
+        if !matches!(field.name(), Some("image") | Some("file")) {
+            continue;
+        }
+        let content_type = field.content_type().map(|content_type| content_type.to_string());
+        let mut bytes = Vec::new();
+        while let Some(chunk) = field.chunk().await.map_err(invalid)? {
+            if bytes.len() + chunk.len() > max {
+                return Err(Error::PayloadTooLarge);
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+        return Ok((bytes, content_type));
+    }
+
+    Err(Error::InvalidInput("Expected an `image` field".to_string()))
+}